@@ -53,8 +53,7 @@ fn main() {
     {
         let f = {
             let f = std::fs::File::create(std::env::temp_dir().join("stime_example_log")).unwrap();
-            let f = CustomLog::new(f);
-            f
+            CustomLog::new(f)
         };
         start!(@f, "start custom log file");
         work!();
@@ -0,0 +1,689 @@
+//! Duration rendering
+
+use crate::advanced::{self, Palette, Record, Round, Unit};
+use scolor::ColorExt;
+use std::{
+    fmt::{self, Display},
+    io,
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+#[doc(hidden)]
+pub struct FDur(pub Duration, pub Option<Unit>);
+impl FDur {
+    pub(crate) fn auto_unit(&self) -> Unit {
+        if self.0.as_secs() != 0 {
+            Unit::S
+        } else if self.0.as_millis() != 0 {
+            Unit::Ms
+        } else if self.0.as_micros() != 0 {
+            Unit::Us
+        } else {
+            Unit::Ns
+        }
+    }
+}
+impl Display for FDur {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(formatter) = &*advanced::FORMATTER.read().unwrap() {
+            return f.write_str(&formatter(self.0));
+        }
+        if advanced::COARSE.load(Ordering::Relaxed) {
+            return self.fmt_coarse(f);
+        }
+        let (unit, forced) = match self.1.unwrap_or_else(|| *advanced::UNIT.lock().unwrap()) {
+            Unit::Auto => (self.auto_unit(), false),
+            Unit::Custom(name) => match custom_unit(name) {
+                Some(nanos) => return self.fmt_custom(f, name, nanos),
+                None => (self.auto_unit(), false),
+            },
+            unit => (unit, true),
+        };
+        let alarm = advanced::ALARM
+            .lock()
+            .unwrap()
+            .filter(|(threshold, _)| self.0 > *threshold)
+            .map(|(_, style)| style);
+        if unit == Unit::RustDebug {
+            if let Some(style) = alarm {
+                return f.write_str(&alarm_style(style, format_args!("{:?}", self.0)));
+            }
+            return paint(f, self.auto_unit(), format_args!("{:?}", self.0));
+        }
+
+        let round = *advanced::ROUNDING.lock().unwrap();
+        let nanos = self.0.as_nanos();
+        if !forced
+            && advanced::CLOCK_STYLE.load(Ordering::Relaxed)
+            && round_div(nanos, 1_000_000_000, round) >= 60
+        {
+            return self.fmt_clock(f, alarm);
+        }
+
+        let compact = advanced::COMPACT.load(Ordering::Relaxed);
+        let unit_nanos = |unit| match unit {
+            Unit::S => 1_000_000_000,
+            Unit::Ms => 1_000_000,
+            Unit::Us => 1_000,
+            _ => 1,
+        };
+        // a forced unit can be bigger than the duration, show fractions so small values don't end up as 0
+        let sigfigs = advanced::SIGFIGS.load(Ordering::Relaxed);
+        let value = |unit| {
+            if sigfigs != 0 {
+                significant(nanos as f64 / unit_nanos(unit) as f64, sigfigs, round)
+            } else if forced {
+                fractional(nanos as f64 / unit_nanos(unit) as f64, round)
+            } else {
+                round_div(nanos, unit_nanos(unit), round).to_string()
+            }
+        };
+        let (mut unit, mut text) = (unit, value(unit));
+        // rounding up can reach the next unit, example: 999_999 ns is `1 ms` and not `1000 us` with Round::Nearest
+        while !forced && unit != Unit::S && text.parse::<f64>().is_ok_and(|value| value >= 1000.0) {
+            unit = match unit {
+                Unit::Ns => Unit::Us,
+                Unit::Us => Unit::Ms,
+                _ => Unit::S,
+            };
+            text = value(unit);
+        }
+        let value = text;
+        let suffix = match unit {
+            Unit::S => "s",
+            Unit::Ms => "ms",
+            Unit::Us if compact => "µs",
+            Unit::Us => "us",
+            _ => "ns",
+        };
+        let space = if compact { "" } else { " " };
+        if let Some(style) = alarm {
+            return f.write_str(&alarm_style(
+                style,
+                format_args!("{}{}{}", value, space, suffix),
+            ));
+        }
+        paint(f, unit, value)?;
+        f.write_str(space)?;
+        paint(f, unit, suffix)
+    }
+}
+impl FDur {
+    /// Render in a unit registered with [advanced::add_unit], it is colored like the automatic unit
+    fn fmt_custom(&self, f: &mut fmt::Formatter, name: &str, unit_nanos: f64) -> fmt::Result {
+        let round = *advanced::ROUNDING.lock().unwrap();
+        let value = self.0.as_nanos() as f64 / unit_nanos;
+        let value = match advanced::SIGFIGS.load(Ordering::Relaxed) {
+            0 => fractional(value, round),
+            sigfigs => significant(value, sigfigs, round),
+        };
+        let plural = if value == "1" { "" } else { "s" };
+        let space = if advanced::COMPACT.load(Ordering::Relaxed) {
+            ""
+        } else {
+            " "
+        };
+        let alarm = advanced::ALARM
+            .lock()
+            .unwrap()
+            .filter(|(threshold, _)| self.0 > *threshold);
+        if let Some((_, style)) = alarm {
+            return f.write_str(&alarm_style(
+                style,
+                format_args!("{}{}{}{}", value, space, name, plural),
+            ));
+        }
+        let unit = self.auto_unit();
+        paint(f, unit, value)?;
+        f.write_str(space)?;
+        paint(f, unit, format_args!("{}{}", name, plural))
+    }
+}
+
+/// The mean of `count` durations summing to `total`, with fractional nanoseconds so sub-nanosecond means don't show as `0 ns`
+pub(crate) struct FMean(pub Duration, pub u64);
+impl Display for FMean {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let nanos = self.0.as_nanos() as f64 / self.1.max(1) as f64;
+        let exact = advanced::FORMATTER.read().unwrap().is_some()
+            || advanced::COARSE.load(Ordering::Relaxed)
+            || *advanced::UNIT.lock().unwrap() != Unit::Auto;
+        if exact || nanos >= 1_000.0 {
+            return FDur(self.0.div_f64(self.1.max(1) as f64), None).fmt(f);
+        }
+        let round = *advanced::ROUNDING.lock().unwrap();
+        let value = match advanced::SIGFIGS.load(Ordering::Relaxed) {
+            0 => fractional(nanos, round),
+            sigfigs => significant(nanos, sigfigs, round),
+        };
+        paint(f, Unit::Ns, value)?;
+        if !advanced::COMPACT.load(Ordering::Relaxed) {
+            f.write_str(" ")?;
+        }
+        paint(f, Unit::Ns, "ns")
+    }
+}
+
+impl FDur {
+    /// Render in hours, minutes and seconds, see [advanced::set_clock_style]
+    fn fmt_clock(&self, f: &mut fmt::Formatter, alarm: Option<advanced::Style>) -> fmt::Result {
+        let secs = round_div(
+            self.0.as_nanos(),
+            1_000_000_000,
+            *advanced::ROUNDING.lock().unwrap(),
+        );
+        let (space, sep) = if advanced::COMPACT.load(Ordering::Relaxed) {
+            ("", "")
+        } else {
+            (" ", " ")
+        };
+        let (hours, minutes, secs) = (secs / 3600, secs % 3600 / 60, secs % 60);
+        let mut parts = Vec::new();
+        if hours != 0 {
+            parts.push(format!("{}{}h", hours, space));
+        }
+        parts.push(format!("{}{}m", minutes, space));
+        parts.push(format!("{}{}s", secs, space));
+        let text = parts.join(sep);
+        match alarm {
+            Some(style) => f.write_str(&alarm_style(style, text)),
+            None => paint(f, Unit::S, text),
+        }
+    }
+    /// Render as a rough value, see [advanced::set_coarse]
+    fn fmt_coarse(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the first unit in which the rounded value is under the next unit
+        const UNITS: [(u128, u128, &str); 4] = [
+            (1_000_000, 1000, "ms"),
+            (1_000_000_000, 60, "s"),
+            (60_000_000_000, 60, "m"),
+            (3_600_000_000_000, u128::MAX, "h"),
+        ];
+        let nanos = self.0.as_nanos();
+        let text = if nanos < 1_000_000 {
+            "<1ms".to_string()
+        } else {
+            UNITS
+                .iter()
+                .map(|(scale, limit, suffix)| ((nanos + scale / 2) / scale, limit, suffix))
+                .find(|(value, limit, _)| value < limit)
+                .map(|(value, _, suffix)| format!("~{}{}", value, suffix))
+                .unwrap_or_default()
+        };
+        let alarm = advanced::ALARM
+            .lock()
+            .unwrap()
+            .filter(|(threshold, _)| self.0 > *threshold);
+        match alarm {
+            Some((_, style)) => f.write_str(&alarm_style(style, text)),
+            None => paint(f, self.auto_unit(), text),
+        }
+    }
+}
+
+/// The duration in nanoseconds of a unit registered with [advanced::add_unit]
+fn custom_unit(name: &str) -> Option<f64> {
+    advanced::CUSTOM_UNITS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|(unit, _)| *unit == name)
+        .map(|(_, duration)| duration.as_nanos() as f64)
+        .filter(|nanos| *nanos > 0.0)
+}
+
+/// A duration over the alarm threshold, in red with the alarm style (see [advanced::set_alarm])
+fn alarm_style(style: advanced::Style, text: impl Display) -> String {
+    let effect = match style {
+        advanced::Style::Bold => "1",
+        advanced::Style::Blink => "1;5",
+        advanced::Style::Inverse => "1;7",
+    };
+    // a value color that scolor can't express (it has no blink)
+    format!("\x1b[0;{};38;2;255;0;0m{}\x1b[0m", effect, text)
+}
+
+/// Color a piece of a duration according to its unit
+fn paint(f: &mut fmt::Formatter, unit: Unit, text: impl Display) -> fmt::Result {
+    if !advanced::COLOR_TARGETS.lock().unwrap().values {
+        return write!(f, "{}", text);
+    }
+    let text = match unit {
+        Unit::S => text.red().to_string(),
+        Unit::Ms => text.yellow().to_string(),
+        Unit::Us => text.green().to_string(),
+        _ => text.rgb_fg(255, 255, 255).to_string(),
+    };
+    f.write_str(&value_color(&text))
+}
+
+/// Mark the color of a value, so it is kept when only labels lose their colors (see [advanced::ColorTargets])
+///
+/// The mark is a leading reset parameter (`ESC[0;..m`), which scolor never emits itself
+pub(crate) fn value_color(colored: &str) -> String {
+    colored.replacen("\x1b[", "\x1b[0;", 1)
+}
+
+/// `nanos` in units of `unit_nanos`, rounded to an integer
+fn round_div(nanos: u128, unit_nanos: u128, round: Round) -> u128 {
+    match round {
+        Round::Down => nanos / unit_nanos,
+        Round::Nearest => (nanos + unit_nanos / 2) / unit_nanos,
+        Round::Up => nanos.div_ceil(unit_nanos),
+    }
+}
+
+fn fractional(value: f64, round: Round) -> String {
+    let decimals = if value == 0.0 || value >= 1.0 {
+        3
+    } else {
+        (2 - value.log10().floor() as i32).clamp(3, 9) as usize
+    };
+    let scale = 10f64.powi(decimals as i32);
+    // the relative epsilon keeps values like 0.3 (0.29999..) from being rounded down
+    let value = match round {
+        Round::Down => (value * scale * (1.0 + 1e-12)).floor() / scale,
+        Round::Nearest => (value * scale).round() / scale,
+        Round::Up => (value * scale * (1.0 - 1e-12)).ceil() / scale,
+    };
+    let text = format!("{:.*}", decimals, value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// `value` with `sigfigs` significant figures, the integer part is always shown in full
+fn significant(value: f64, sigfigs: u32, round: Round) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let rounded = |digits: i32| {
+        let decimals = (sigfigs as i32 - digits).clamp(0, 9) as usize;
+        let scale = 10f64.powi(decimals as i32);
+        let value = match round {
+            Round::Down => (value * scale * (1.0 + 1e-12)).floor() / scale,
+            Round::Nearest => (value * scale).round() / scale,
+            Round::Up => (value * scale * (1.0 - 1e-12)).ceil() / scale,
+        };
+        (decimals, value)
+    };
+    let digits = value.log10().floor() as i32 + 1;
+    let (mut decimals, mut value) = rounded(digits);
+    // rounding up can carry into a new digit, example: 9.996 is 10.0 and not 10.00 with 3 figures
+    if value >= 10f64.powi(digits) {
+        (decimals, value) = rounded(digits + 1);
+    }
+    format!("{:.*}", decimals, value)
+}
+
+/// The record of a check in [advanced::Format::Binary]
+pub(crate) fn binary_record(total: Duration, delta: Duration, msg: &dyn Display) -> Vec<u8> {
+    let msg = msg.to_string();
+    let mut len = msg.len().min(u16::MAX as usize);
+    while !msg.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut record = Vec::with_capacity(18 + len);
+    record.extend_from_slice(&(total.as_nanos() as u64).to_le_bytes());
+    record.extend_from_slice(&(delta.as_nanos() as u64).to_le_bytes());
+    record.extend_from_slice(&(len as u16).to_le_bytes());
+    record.extend_from_slice(&msg.as_bytes()[..len]);
+    record
+}
+
+/// Decode a log written with [advanced::Format::Binary]
+///
+/// The binary format doesn't keep threads, so the records have an empty `thread`, and their `at` is the time they were decoded
+pub fn read_binary_log(mut reader: impl io::Read) -> io::Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut header = [0; 18];
+    loop {
+        // a clean end of the log is only possible between records
+        match reader.read(&mut header[..1])? {
+            0 => return Ok(records),
+            _ => reader.read_exact(&mut header[1..])?,
+        }
+        let total = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let delta = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let len = u16::from_le_bytes(header[16..].try_into().unwrap());
+        let mut msg = vec![0; len as usize];
+        reader.read_exact(&mut msg)?;
+        let msg =
+            String::from_utf8(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        records.push(Record::decoded(
+            msg,
+            Duration::from_nanos(total),
+            Duration::from_nanos(delta),
+        ));
+    }
+}
+
+/// The line identifying the process, see [advanced::set_header]
+pub(crate) fn header() -> String {
+    let binary = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let started = utc(advanced::run_start());
+    if advanced::format() == advanced::Format::Json {
+        return JsonLine::new("header")
+            .str("binary", &binary)
+            .num("pid", std::process::id())
+            .str("started", &started)
+            .finish();
+    }
+    format!(
+        "# stime: {} (pid {}) started at {}",
+        binary,
+        std::process::id(),
+        started
+    )
+}
+
+/// A time as an ISO 8601 UTC date, example: `2024-05-01T12:30:05Z`
+fn utc(time: std::time::SystemTime) -> String {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // the civil date of a day count, from Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// A number with an SI suffix (K, M, G or T) and one decimal, example: `2.0M`
+pub(crate) fn si(value: f64) -> String {
+    const SUFFIXES: [(f64, &str); 4] = [(1e12, "T"), (1e9, "G"), (1e6, "M"), (1e3, "K")];
+    match SUFFIXES.iter().find(|(scale, _)| value.abs() >= *scale) {
+        Some((scale, suffix)) => format!("{:.1}{}", value / scale, suffix),
+        None => format!("{:.1}", value),
+    }
+}
+
+/// Rewrite the truecolor escapes (which is what scolor emits) of `line` to `palette`
+pub(crate) fn apply_palette(line: &str, palette: Palette) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find("\x1b[") {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find('m') else {
+            out.push_str("\x1b[");
+            break;
+        };
+        let params: Vec<&str> = rest[..end].split(';').collect();
+        rest = &rest[end + 1..];
+        let mut mapped = Vec::with_capacity(params.len());
+        let mut i = 0;
+        while i < params.len() {
+            match (params[i], params.get(i + 1), params.get(i + 2..i + 5)) {
+                (kind @ ("38" | "48"), Some(&"2"), Some([r, g, b])) => {
+                    let (r, g, b) = (
+                        r.parse().unwrap_or(0),
+                        g.parse().unwrap_or(0),
+                        b.parse().unwrap_or(0),
+                    );
+                    mapped.push(map_color(kind == "48", r, g, b, palette));
+                    i += 5;
+                }
+                (param, _, _) => {
+                    mapped.push(param.to_string());
+                    i += 1;
+                }
+            }
+        }
+        out.push_str("\x1b[");
+        out.push_str(&mapped.join(";"));
+        out.push('m');
+    }
+    out.push_str(rest);
+    out
+}
+
+fn map_color(bg: bool, r: u8, g: u8, b: u8, palette: Palette) -> String {
+    match palette {
+        Palette::TrueColor => format!("{};2;{};{};{}", if bg { 48 } else { 38 }, r, g, b),
+        Palette::Ansi256 => {
+            // the 6x6x6 color cube
+            let level = |c: u8| (c as u16 * 5 + 127) / 255;
+            let index = 16 + 36 * level(r) + 6 * level(g) + level(b);
+            format!("{};5;{}", if bg { 48 } else { 38 }, index)
+        }
+        Palette::Ansi16 => {
+            let index = (r >= 128) as u8 | ((g >= 128) as u8) << 1 | ((b >= 128) as u8) << 2;
+            let bright = r.max(g).max(b) > 191;
+            let base = match (bg, bright) {
+                (false, false) => 30,
+                (false, true) => 90,
+                (true, false) => 40,
+                (true, true) => 100,
+            };
+            (base + index).to_string()
+        }
+    }
+}
+
+/// Builder of a json line, used by [advanced::Format::Json]
+pub(crate) struct JsonLine(String);
+impl JsonLine {
+    pub(crate) fn new(event: &str) -> Self {
+        let mut line = JsonLine(String::from("{"));
+        line = line.str("run_id", &advanced::run_id()).str("event", &event);
+        if let Some(session) = advanced::SESSION.with(|s| s.borrow().clone()) {
+            line = line.str("session", &session);
+        }
+        line
+    }
+    fn key(&mut self, key: &str) {
+        if self.0.len() > 1 {
+            self.0.push(',');
+        }
+        self.0.push('"');
+        self.0.push_str(key);
+        self.0.push_str("\":");
+    }
+    pub(crate) fn str(mut self, key: &str, value: &dyn Display) -> Self {
+        self.key(key);
+        self.0.push('"');
+        for c in value.to_string().chars() {
+            match c {
+                '"' => self.0.push_str("\\\""),
+                '\\' => self.0.push_str("\\\\"),
+                '\n' => self.0.push_str("\\n"),
+                '\r' => self.0.push_str("\\r"),
+                '\t' => self.0.push_str("\\t"),
+                c if c.is_control() => self.0.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.0.push(c),
+            }
+        }
+        self.0.push('"');
+        self
+    }
+    /// Non finite numbers (like the rate of an empty interval) aren't valid json, they are written as `null`
+    pub(crate) fn num(mut self, key: &str, value: impl Display) -> Self {
+        self.key(key);
+        match value.to_string() {
+            value if matches!(&*value, "NaN" | "inf" | "-inf") => self.0.push_str("null"),
+            value => self.0.push_str(&value),
+        }
+        self
+    }
+    pub(crate) fn bool(mut self, key: &str, value: bool) -> Self {
+        self.key(key);
+        self.0.push_str(if value { "true" } else { "false" });
+        self
+    }
+    pub(crate) fn finish(mut self) -> String {
+        self.0.push('}');
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // the settings are global, so the tests changing them don't run concurrently
+    static SETTINGS: Mutex<()> = Mutex::new(());
+
+    fn render(nanos: u64, round: Round, clock_style: bool) -> String {
+        render_sigfigs(nanos, round, clock_style, 0)
+    }
+
+    fn render_sigfigs(nanos: u64, round: Round, clock_style: bool, sigfigs: u32) -> String {
+        let _settings = SETTINGS.lock().unwrap_or_else(|e| e.into_inner());
+        advanced::reset_config();
+        advanced::set_rounding(round);
+        advanced::set_clock_style(clock_style);
+        advanced::set_sigfigs(sigfigs);
+        let text = crate::strip_ansi(&FDur(Duration::from_nanos(nanos), None).to_string());
+        advanced::reset_config();
+        text
+    }
+
+    #[test]
+    fn rounding_picks_the_unit_after_rounding() {
+        assert_eq!(render(999_999, Round::Nearest, false), "1 ms");
+        assert_eq!(render(999_499, Round::Nearest, false), "999 us");
+        assert_eq!(render(999_999, Round::Down, false), "999 us");
+        assert_eq!(render(999_001, Round::Up, false), "1 ms");
+        assert_eq!(render(999, Round::Up, false), "999 ns");
+        assert_eq!(render(999_999_999, Round::Nearest, false), "1 s");
+    }
+
+    #[test]
+    fn clock_style_after_rounding() {
+        assert_eq!(render(59_400_000_000, Round::Nearest, true), "59 s");
+        assert_eq!(render(59_600_000_000, Round::Nearest, true), "1 m 0 s");
+        assert_eq!(
+            render(3_599_600_000_000, Round::Nearest, true),
+            "1 h 0 m 0 s"
+        );
+        assert_eq!(render(59_600_000_000, Round::Down, true), "59 s");
+    }
+
+    #[test]
+    fn significant_carries_into_a_new_digit() {
+        assert_eq!(significant(9.996, 3, Round::Nearest), "10.0");
+        assert_eq!(significant(99.96, 3, Round::Nearest), "100");
+        assert_eq!(significant(9.996, 3, Round::Down), "9.99");
+        assert_eq!(significant(1.234, 3, Round::Nearest), "1.23");
+        assert_eq!(significant(1234.5, 3, Round::Nearest), "1235");
+    }
+
+    #[test]
+    fn significant_picks_the_unit_after_rounding() {
+        assert_eq!(render_sigfigs(999_999, Round::Nearest, false, 3), "1.00 ms");
+        assert_eq!(render_sigfigs(999_999, Round::Down, false, 3), "999 us");
+        assert_eq!(render_sigfigs(9_996, Round::Nearest, false, 3), "10.0 us");
+    }
+
+    #[test]
+    fn json_writes_non_finite_numbers_as_null() {
+        let line = JsonLine(String::from("{"))
+            .num("rate", f64::NAN)
+            .num("max", f64::INFINITY)
+            .num("min", f64::NEG_INFINITY)
+            .num("n", 1.5)
+            .finish();
+        assert_eq!(line, r#"{"rate":null,"max":null,"min":null,"n":1.5}"#);
+    }
+
+    #[test]
+    fn binary_records_round_trip() {
+        let mut log = binary_record(Duration::from_millis(5), Duration::from_micros(7), &"parse");
+        log.extend(binary_record(
+            Duration::from_secs(1),
+            Duration::ZERO,
+            &"héllo",
+        ));
+        let records = read_binary_log(&log[..]).unwrap();
+        let decoded: Vec<_> = records
+            .iter()
+            .map(|r| (r.msg.as_str(), r.total, r.delta))
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                ("parse", Duration::from_millis(5), Duration::from_micros(7)),
+                ("héllo", Duration::from_secs(1), Duration::ZERO),
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_record_truncates_the_message_on_a_char_boundary() {
+        // the 2 bytes of the é straddle the 65535 bytes limit
+        let msg = format!("{}é", "a".repeat(u16::MAX as usize - 1));
+        let record = binary_record(Duration::ZERO, Duration::ZERO, &msg);
+        let records = read_binary_log(&record[..]).unwrap();
+        assert_eq!(records[0].msg, "a".repeat(u16::MAX as usize - 1));
+    }
+
+    #[test]
+    fn binary_log_with_a_truncated_record_is_an_error() {
+        let mut log = binary_record(Duration::ZERO, Duration::ZERO, &"first");
+        log.extend(binary_record(Duration::ZERO, Duration::ZERO, &"second"));
+        for cut in [log.len() - 1, log.len() - 6 - 2] {
+            let error = read_binary_log(&log[..cut]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn json_escapes_strings() {
+        let line = JsonLine(String::from("{"))
+            .str("msg", &"a \"quote\" \\ \n\t\r \u{1} é")
+            .finish();
+        assert_eq!(line, r#"{"msg":"a \"quote\" \\ \n\t\r \u0001 é"}"#);
+    }
+
+    #[test]
+    fn palette_rewrites_truecolor_escapes() {
+        let line = "\x1b[1;38;2;255;0;0mred\x1b[0m \x1b[48;2;0;0;255mblue";
+        assert_eq!(apply_palette(line, Palette::TrueColor), line);
+        assert_eq!(
+            apply_palette(line, Palette::Ansi256),
+            "\x1b[1;38;5;196mred\x1b[0m \x1b[48;5;21mblue"
+        );
+        assert_eq!(
+            apply_palette(line, Palette::Ansi16),
+            "\x1b[1;91mred\x1b[0m \x1b[104mblue"
+        );
+    }
+
+    #[test]
+    fn strip_ansi_keeps_the_selected_escapes() {
+        let line = "\x1b[2K\x1b[1mbold\x1b[0m \x1b[38;2;1;2;3mtext\x1b[1A";
+        assert_eq!(crate::strip_ansi(line), "bold text");
+        assert_eq!(
+            crate::strip_ansi_except(line, |escape| escape.ends_with('m')),
+            "\x1b[1mbold\x1b[0m \x1b[38;2;1;2;3mtext"
+        );
+    }
+
+    #[test]
+    fn utc_formats_civil_dates() {
+        let at = |secs| utc(std::time::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_714_566_605), "2024-05-01T12:30:05Z");
+    }
+}
@@ -0,0 +1,372 @@
+//! A gzip compressing output target, enabled by the `gzip` feature
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+/// The uncompressed size of a gzip member, the file is valid up to the last complete member
+const MEMBER_SIZE: usize = 64 * 1024;
+
+/// A file target compressing its lines with gzip, example: `start!(@GzipTarget::create("run.log.gz")?)`
+///
+/// The lines are buffered and written as a complete gzip member every 64 KiB, on flush and on drop
+/// (the members form one valid gzip file, readable with `zcat`).
+/// [crate::advanced::OUTPUT_TARGET] is never dropped, so call `OUTPUT_TARGET.reset()` before exiting,
+/// or the lines since the last member are lost
+pub struct GzipTarget {
+    file: File,
+    buffer: Vec<u8>,
+}
+
+impl GzipTarget {
+    /// Create (or truncate) the file at `path`
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            file: File::create(path)?,
+            buffer: Vec::with_capacity(MEMBER_SIZE),
+        })
+    }
+
+    fn write_member(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let member = gzip(&self.buffer);
+        self.buffer.clear();
+        self.file.write_all(&member)
+    }
+}
+
+impl Write for GzipTarget {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= MEMBER_SIZE {
+            self.write_member()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_member()?;
+        self.file.flush()
+    }
+}
+
+impl Drop for GzipTarget {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// A complete gzip member holding `data`
+fn gzip(data: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, no mtime, no extra flags, unknown os
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    deflate(data, &mut out);
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    static TABLE: once_cell::sync::Lazy<[u32; 256]> = once_cell::sync::Lazy::new(|| {
+        let mut table = [0; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        table
+    });
+    !data.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Writes bits least significant first, like deflate expects
+struct Bits<'a> {
+    out: &'a mut Vec<u8>,
+    acc: u32,
+    len: u32,
+}
+
+impl Bits<'_> {
+    fn put(&mut self, value: u32, len: u32) {
+        self.acc |= value << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Huffman codes are packed most significant bit first
+    fn code(&mut self, code: u32, len: u32) {
+        self.put(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn literal(&mut self, value: u32) {
+        match value {
+            0..=143 => self.code(0x30 + value, 8),
+            144..=255 => self.code(0x190 + value - 144, 9),
+            256..=279 => self.code(value - 256, 7),
+            _ => self.code(0xc0 + value - 280, 8),
+        }
+    }
+
+    fn finish(self) {
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const WINDOW: usize = 32 * 1024;
+const MAX_CHAIN: usize = 64;
+
+/// Compress `data` as a single final deflate block with the fixed Huffman codes, matches are found with hash chains
+fn deflate(data: &[u8], out: &mut Vec<u8>) {
+    let mut bits = Bits {
+        out,
+        acc: 0,
+        len: 0,
+    };
+    // final block, fixed codes
+    bits.put(0b011, 3);
+    let hash = |i: usize| {
+        ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7fff
+    };
+    // the last position of each hash, and the previous position with the same hash of each position
+    let mut head = vec![usize::MAX; 0x8000];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + 3 <= data.len() {
+            prev[i] = head[hash(i)];
+            head[hash(i)] = i;
+        }
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + 3 <= data.len() {
+            let mut candidate = head[hash(i)];
+            let max_len = (data.len() - i).min(258);
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || i - candidate > WINDOW {
+                    break;
+                }
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    (best_len, best_dist) = (len, i - candidate);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+            }
+        }
+        if best_len >= 3 {
+            let code = LENGTH_BASE.partition_point(|&base| base as usize <= best_len) - 1;
+            bits.literal(257 + code as u32);
+            bits.put(
+                (best_len - LENGTH_BASE[code] as usize) as u32,
+                LENGTH_EXTRA[code].into(),
+            );
+            let code = DIST_BASE.partition_point(|&base| base as usize <= best_dist) - 1;
+            bits.code(code as u32, 5);
+            bits.put(
+                (best_dist - DIST_BASE[code] as usize) as u32,
+                DIST_EXTRA[code].into(),
+            );
+            for j in i..i + best_len {
+                insert(j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            bits.literal(data[i].into());
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    bits.literal(256);
+    bits.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads bits least significant first
+    struct Reader<'a> {
+        data: &'a [u8],
+        bit: usize,
+    }
+
+    impl Reader<'_> {
+        fn bits(&mut self, len: u32) -> u32 {
+            (0..len).fold(0, |value, i| {
+                let bit = self.data[self.bit / 8] >> (self.bit % 8) & 1;
+                self.bit += 1;
+                value | (bit as u32) << i
+            })
+        }
+
+        /// Huffman codes are read most significant bit first
+        fn code(&mut self, len: u32) -> u32 {
+            (0..len).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        fn literal(&mut self) -> u32 {
+            let code = self.code(7);
+            if code <= 0b001_0111 {
+                return 256 + code;
+            }
+            let code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xbf => code - 0x30,
+                0xc0..=0xc7 => 280 + code - 0xc0,
+                _ => 144 + (code << 1 | self.bits(1)) - 0x190,
+            }
+        }
+    }
+
+    /// Decompress the members written by [gzip], which only use fixed Huffman blocks
+    fn gunzip(mut data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        while !data.is_empty() {
+            assert_eq!(data[..4], [0x1f, 0x8b, 8, 0]);
+            let mut reader = Reader {
+                data: &data[10..],
+                bit: 0,
+            };
+            assert_eq!(reader.bits(3), 0b011, "a final block with fixed codes");
+            let start = out.len();
+            loop {
+                let literal = reader.literal();
+                match literal {
+                    0..=255 => out.push(literal as u8),
+                    256 => break,
+                    _ => {
+                        let code = (literal - 257) as usize;
+                        let len = LENGTH_BASE[code] as usize
+                            + reader.bits(LENGTH_EXTRA[code].into()) as usize;
+                        let code = reader.code(5) as usize;
+                        let dist = DIST_BASE[code] as usize
+                            + reader.bits(DIST_EXTRA[code].into()) as usize;
+                        assert!(dist <= out.len() - start, "a match before the member");
+                        for _ in 0..len {
+                            out.push(out[out.len() - dist]);
+                        }
+                    }
+                }
+            }
+            let end = 10 + reader.bit.div_ceil(8);
+            let member = &out[start..];
+            assert_eq!(data[end..end + 4], crc32(member).to_le_bytes());
+            assert_eq!(data[end + 4..end + 8], (member.len() as u32).to_le_bytes());
+            data = &data[end + 8..];
+        }
+        out
+    }
+
+    /// Pseudo random bytes
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 1u32;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let compressed = gzip(data);
+        assert_eq!(gunzip(&compressed), data);
+        compressed
+    }
+
+    #[test]
+    fn crc32_of_a_known_input() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn empty_input() {
+        round_trip(b"");
+    }
+
+    #[test]
+    fn every_literal() {
+        // every byte value occurs, with few matches
+        round_trip(&noise(10_000));
+    }
+
+    #[test]
+    fn repetitive_input_uses_maximum_length_matches() {
+        let compressed = round_trip(&[b'a'; 10_000]);
+        // one literal then matches of 258 bytes
+        assert!(compressed.len() < 100, "{} bytes", compressed.len());
+        let lines = "[TotalTime: 1 ms / DeltaTime: 2 us] check\n".repeat(1000);
+        round_trip(lines.as_bytes());
+        // matches at the largest distance: the start of the window repeated
+        let mut data = noise(WINDOW);
+        data.extend_from_within(..1000);
+        round_trip(&data);
+    }
+
+    #[test]
+    fn matches_of_every_length() {
+        let block = noise(300);
+        let data: Vec<u8> = (3..=258)
+            .flat_map(|len| [&block[..len], b"|"].concat())
+            .collect();
+        round_trip(&data);
+    }
+
+    #[test]
+    fn target_writes_a_member_every_64_kib() {
+        let path = std::env::temp_dir().join(format!("stime-gzip-{}.gz", std::process::id()));
+        let data: Vec<u8> = (0..3 * MEMBER_SIZE as u32 / 2)
+            .flat_map(|i| format!("line {}\n", i % 1000).into_bytes())
+            .collect();
+        let mut target = GzipTarget::create(&path).unwrap();
+        for chunk in data.chunks(1000) {
+            target.write_all(chunk).unwrap();
+        }
+        drop(target);
+        let compressed = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(gunzip(&compressed), data);
+    }
+}
@@ -4,13 +4,42 @@
 //!Easy API to time code.
 //!
 //!
-//!It exposes 2 macros:
+//!It exposes these macros:
 //!- [start] => start the timer
+//!- [start_to] => start the timer with an output target that is only used until the next start
 //!- [check] => print the elapsed duration since the last start (and the delta between checks)
+//!- [measure] => restart the timer, evaluate an expression and check it
+//!- [try_timed] => time a fallible expression
+//!- [timed_var] => time the initializer of a variable, labeled with its name
+//!- [measure_drop] => consume a value and time its drop
+//!- [check_if] => check only when a condition holds
+//!- [check_if_slow] => check only when the delta is over a threshold
+//!- [check_to] => check to a given writer
+//!- [eprintln_timed] => eprintln with the elapsed time as prefix
+//!- [check_fmt] => check and return the line instead of printing it
+//!- [peek] => like check but without advancing the delta baseline
+//!- [global_mark] => set a mark that checks can measure from, across starts
+//!- [zero] => print a reference point that the next deltas are counted from
+//!- [bench] => run an expression many times and print the time per iteration and the ops/s
+//!- [progress] => print the progress of a long operation against its expected duration
+//!- [check_rate] => check and print the throughput of the delta
+//!- [recursion] => time each level of a recursion, with its depth
+//!- [span_start] and [span_end] => time the region between two points, matched by name
 //!
 //!By default these macros are no-op, they are only activated if the environment variable
 //!`STIME` is set, example: `STIME=1`
 //!
+//!`STIME=list` lists every reached [start] and [check] call site once, without timing anything.
+//!
+//!Some settings can also be given through environment variables, they override the defaults and are themselves
+//!overridden by the functions of the advanced module:
+//!- `STIME_FORMAT=human|json|binary`, see [advanced::set_format]
+//!- `STIME_COLOR=0|1`, see [advanced::set_color]
+//!- `STIME_UNIT=s|ms|us|ns|debug|auto`, see [advanced::set_unit]
+//!- `STIME_TAGS=io,db` only prints the checks with one of these tags (and the untagged ones), see [advanced::set_tag_filter]
+//!
+//!With the default `macros` feature, functions can be timed as a whole with the [macro@timed] attribute.
+//!
 //!There are also some convenience methods under advanced module.
 //!
 //!
@@ -31,13 +60,70 @@
 
 use once_cell::sync::Lazy;
 use std::{
-    sync::Mutex,
+    borrow::Cow,
+    collections::HashMap,
+    sync::{atomic::AtomicBool, Mutex},
     time::{Duration, Instant},
 };
 
 #[doc(hidden)]
 pub use scolor::*;
 
+/// ```rust
+/// # stime::STIME_ACTIVE.store(true, std::sync::atomic::Ordering::Relaxed);
+/// # stime::advanced::set_color(false);
+/// use stime::timed;
+/// use std::{future::Future, num::ParseIntError, pin::pin, task::{Context, Poll, Waker}};
+///
+/// struct Parser;
+/// impl Parser {
+///     #[timed]
+///     fn parse(&self, input: &str) -> Result<u32, ParseIntError> {
+///         if input.is_empty() {
+///             return Ok(0);
+///         }
+///         let n = input.trim().parse()?;
+///         Ok(n)
+///     }
+/// }
+///
+/// #[timed("largest item")]
+/// fn largest<T: PartialOrd + Copy>(items: &[T]) -> Option<T> {
+///     items.iter().copied().reduce(|a, b| if b > a { b } else { a })
+/// }
+///
+/// #[timed]
+/// async fn fetch() -> u32 {
+///     7
+/// }
+///
+/// stime::start!();
+/// let log = stime::advanced::capture();
+/// assert_eq!(Parser.parse(""), Ok(0));
+/// assert_eq!(Parser.parse("42"), Ok(42));
+/// assert!(Parser.parse("x").is_err());
+/// assert_eq!(largest(&[1, 3, 2]), Some(3));
+/// let mut future = pin!(fetch());
+/// let poll = future.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+/// assert_eq!(poll, Poll::Ready(7));
+///
+/// let log = log.contents();
+/// assert_eq!(log.matches("parse: ").count(), 3);
+/// assert_eq!(log.matches("largest item: ").count(), 1);
+/// assert_eq!(log.matches("fetch: ").count(), 1);
+/// ```
+#[cfg(feature = "macros")]
+pub use stime_macros::timed;
+
+mod format;
+#[cfg(feature = "gzip")]
+mod gzip;
+mod record;
+mod sys;
+
+#[doc(hidden)]
+pub use format::FDur;
+
 #[doc(hidden)]
 pub static CHRONO: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
 
@@ -45,13 +131,24 @@ pub static CHRONO: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()
 pub static LAST_DURATION: Lazy<Mutex<Option<Duration>>> = Lazy::new(|| Mutex::new(None));
 
 #[doc(hidden)]
-pub static STIME_ACTIVE: Lazy<bool> = Lazy::new(|| std::env::var("STIME").is_ok());
+pub static STARTED: AtomicBool = AtomicBool::new(false);
+
+#[doc(hidden)]
+pub static WARNED_NOT_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[doc(hidden)]
+pub static STIME_ACTIVE: Lazy<AtomicBool> =
+    Lazy::new(|| AtomicBool::new(std::env::var("STIME").is_ok()));
+
+#[doc(hidden)]
+pub fn active() -> bool {
+    STIME_ACTIVE.load(std::sync::atomic::Ordering::Relaxed)
+}
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! rtry {
     ($e: expr) => {{
-        use $crate::{advanced::*, *};
         if let Err(e) =
             (|| -> ::std::result::Result<(), ::std::boxed::Box<dyn ::std::error::Error>> { $e })()
         {
@@ -67,200 +164,3441 @@ macro_rules! rtry {
 /// `start` can accept an optional message that implement [std::fmt::Display] to show, if no message is given it will display `file_name:call_line` instead
 ///
 /// @modifier can be used to specify output target, example: start!(@std::io::stdout());
+/// the target is kept for all the following output until it is replaced, without it the output goes back to stderr
+/// (so targets installed with [advanced::OUTPUT_TARGET] or [advanced::capture] go after the `start`),
+/// see [start_to] to only use a target until the next `start`
 #[macro_export]
 macro_rules! start {
     () => {
-        start!(concat!(file!(), ":", line!()));
+        $crate::start!($crate::__default_label!());
     };
     (@$target: expr) => {
-        start!(@target, concat!(file!(), ":", line!()));
+        $crate::start!(@$target, $crate::__default_label!());
     };
     ($msg: expr) => {
         $crate::start!(@::std::io::stderr(), $msg);
     };
     (@$target: expr, $msg: expr) => {
        $crate::rtry!({
-            use ::std::io::Write;
-            if !&*STIME_ACTIVE {
+            if !$crate::active() {
                 return Ok(())
             }
-            *CHRONO.lock()? = ::std::time::Instant::now();
-            *LAST_DURATION.lock()? = None;
-            let mut target = $target;
-            writeln!(&mut target, "{} {}", "Starting".red().bold(), $msg.light_blue().italic())?;
-            *OUTPUT_TARGET.get() = Box::new(target);
-            Ok(())
+            $crate::restart()?;
+            $crate::advanced::OUTPUT_TARGET.set($target);
+            $crate::starting(concat!(file!(), ":", line!()), &$msg)
         });
     };
 }
 
-/// Prints the elapsed time since the last call to [start] (and the delta between checks)
+/// Like [start] with an @target, but the target is only used until the next [start] (or [measure]),
+/// which puts the previous target back
+///
+/// Example: `start_to!(@std::fs::File::create("import.log")?, "import");`
+#[macro_export]
+macro_rules! start_to {
+    (@$target: expr) => {
+        $crate::start_to!(@$target, concat!(file!(), ":", line!()));
+    };
+    (@$target: expr, $msg: expr) => {
+       $crate::rtry!({
+            if !$crate::active() {
+                return Ok(())
+            }
+            $crate::restart()?;
+            $crate::advanced::OUTPUT_TARGET.set_scoped($target);
+            $crate::starting(concat!(file!(), ":", line!()), &$msg)
+        });
+    };
+}
+
+/// Restart the timer and time an expression, returning its value
 ///
-/// If [start] was not called yet it will print the elapsed time from the program start
+/// This is a shorthand for `start` (without the "Starting" line), the expression and a `check` labeled with `msg`,
+/// subsequent calls to [check] are relative to the restarted timer
 ///
-/// `check` can accept an optional message that implement [std::fmt::Display] to show, if no message is given it will display `file_name:call_line` instead
+/// When stime is inactive it only evaluates the expression
 #[macro_export]
-macro_rules! check {
+macro_rules! measure {
+    ($msg: expr, $e: expr) => {{
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            $crate::restart()
+        });
+        let value = $e;
+        $crate::check!($msg);
+        value
+    }};
+}
+
+/// Run an expression `iterations` times and print the time per iteration and the throughput
+///
+/// Example: `bench!("parse", 10_000, parse(input))` prints `parse: 10000 iterations in 12 ms, 1.2 us/iter (833.3K ops/s)`
+///
+/// The result of each iteration goes through [std::hint::black_box] so it is not optimized away,
+/// when stime is inactive nothing is run
+#[macro_export]
+macro_rules! bench {
+    ($msg: expr, $iterations: expr, $e: expr) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            if $crate::listed(concat!(file!(), ":", line!()), &$msg)? {
+                return Ok(());
+            }
+            let iterations: u64 = $iterations;
+            let start = ::std::time::Instant::now();
+            for _ in 0..iterations {
+                ::std::hint::black_box($e);
+            }
+            $crate::print_bench(&$msg, iterations, start.elapsed())
+        });
+    };
+}
+
+/// Consume a value and print how long dropping it took, to find expensive [Drop] impls
+///
+/// It is labeled with the expression, example: `measure_drop!(cache)` prints `drop cache: 40 ms`,
+/// or with the given message: `measure_drop!("free the cache", cache)`
+///
+/// When stime is inactive the value is only dropped
+#[macro_export]
+macro_rules! measure_drop {
+    ($value: expr) => {
+        $crate::measure_drop!(concat!("drop ", stringify!($value)), $value)
+    };
+    ($msg: expr, $value: expr) => {{
+        let value = $value;
+        let start = ::std::time::Instant::now();
+        ::std::mem::drop(value);
+        let elapsed_time = start.elapsed();
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            if $crate::listed(concat!(file!(), ":", line!()), &$msg)? {
+                return Ok(());
+            }
+            $crate::print_timed(&$msg, elapsed_time, true)
+        });
+    }};
+}
+
+/// Like [eprintln] but the line is prefixed with the elapsed time since the last [start], example: `[+12 ms] retrying`
+///
+/// It is a drop-in replacement for existing `eprintln!` calls: the line is always written to stderr,
+/// without the prefix when stime is inactive
+#[macro_export]
+macro_rules! eprintln_timed {
+    () => {
+        $crate::eprint_timed(format_args!(""))
+    };
+    ($($args: tt)*) => {
+        $crate::eprint_timed(format_args!($($args)*))
+    };
+}
+
+/// Like [check] but writes the line to the given writer instead of the output target, the same as `check!(@target, ..)`
+///
+/// The writer is only used for this call, example: `check_to!(&mut buf, "msg")`, the options, message and fields of [check] can follow it
+#[macro_export]
+macro_rules! check_to {
+    ($target: expr $(, $($args: tt)*)?) => {
+        $crate::check!(@$target $(, $($args)*)?)
+    };
+}
+
+/// Like [check] but returns the line as a [String] (with its colors) instead of writing it, to embed it in your own output
+///
+/// Example: `println!("{} | rows: {}", check_fmt!("load"), rows)`, when stime is inactive the line is empty
+#[macro_export]
+macro_rules! check_fmt {
     () => {
-        check!(concat!(file!(), ":", line!()));
+        $crate::check_fmt!($crate::__default_label!())
     };
     ($msg: expr) => {
+        if $crate::active() {
+            $crate::check_string(
+                &$msg,
+                &$crate::CheckOpts::new(concat!(file!(), ":", line!())),
+            )
+            .unwrap_or_default()
+        } else {
+            ::std::string::String::new()
+        }
+    };
+}
+
+/// Like [check] but only when `cond` is true, example: `check_if!(retries > 0, "retried", retries = retries)`
+///
+/// The options, message and fields of [check] follow the condition. When it is false nothing is updated,
+/// the delta of the next check spans from the previous one. The condition is not evaluated when stime is inactive
+#[macro_export]
+macro_rules! check_if {
+    ($cond: expr $(, $($args: tt)*)?) => {
+        if $crate::active() && $cond {
+            $crate::check!($($($args)*)?);
+        }
+    };
+}
+
+/// Like [check] but only prints if the delta exceeds `threshold`, the message is built lazily by the given closure
+///
+/// The delta baseline is advanced either way, example: `check_if_slow!(Duration::from_millis(10), || format!("slow: {}", id))`
+#[macro_export]
+macro_rules! check_if_slow {
+    ($threshold: expr, $msg: expr) => {
         $crate::rtry!({
-            if !&*STIME_ACTIVE {
+            if !$crate::active() {
                 return Ok(());
             }
-            let total_time = CHRONO.lock()?.elapsed();
-            let delta = if let Some(last_dur) = *LAST_DURATION.lock()? {
-                total_time - last_dur
-            } else {
-                total_time
-            };
-            *LAST_DURATION.lock()? = Some(total_time);
-
-            writeln!(
-                OUTPUT_TARGET.get(),
-                //[T  ti  /  D  ti]  msg
-                "{}{} {} {} {} {}{} {}",
-                "[".light_blue(),
-                "TotalTime:".bold(),
-                FDur(total_time),
-                "/".light_blue(),
-                "DeltaTime:".bold(),
-                FDur(delta),
-                "]".light_blue(),
-                $msg.light_blue().italic()
-            )
-            .map_err(Into::into)
+            let mut opts = $crate::CheckOpts::new(concat!(file!(), ":", line!()));
+            opts.slower_than = Some($threshold);
+            $crate::checkpoint(&$crate::LazyMsg($msg), opts)
         });
     };
 }
 
-/// Convenient utilities for advanced use-cases
-pub mod advanced {
-    use crate::FDur;
-    use once_cell::sync::Lazy;
-    use scolor::ColorExt;
-    use std::{
-        io,
-        sync::{Arc, Mutex, MutexGuard},
-        time::Instant,
-    };
+/// Time a fallible expression and return its [Result], so it can be used with `?`
+///
+/// The elapsed time is printed for both `Ok` and `Err` (in red), example: `let body = try_timed!("fetch", fetch())?;`
+///
+/// When stime is inactive it only evaluates the expression
+#[macro_export]
+macro_rules! try_timed {
+    ($msg: expr, $e: expr) => {{
+        let start = ::std::time::Instant::now();
+        let result = $e;
+        let elapsed_time = start.elapsed();
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            if $crate::listed(concat!(file!(), ":", line!()), &$msg)? {
+                return Ok(());
+            }
+            $crate::print_timed(&$msg, elapsed_time, ::std::result::Result::is_ok(&result))
+        });
+        result
+    }};
+}
 
-    /// The output target of all logging functions, it defaults to stderr
-    pub static OUTPUT_TARGET: Lazy<Target> = Lazy::new(Target::new);
+/// Like [check] but the delta baseline is not advanced, so the delta of the next [check] still spans from before the peek
+///
+/// Useful to print the elapsed time in the middle of a phase without splitting it
+#[macro_export]
+macro_rules! peek {
+    () => {
+        $crate::peek!(concat!(file!(), ":", line!()));
+    };
+    ($msg: expr) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            let mut opts = $crate::CheckOpts::new(concat!(file!(), ":", line!()));
+            opts.peek = true;
+            $crate::checkpoint(&$msg, opts)
+        });
+    };
+}
 
-    /// The output target of all logging functions, it defaults to stderr
-    pub struct Target {
-        inner: Mutex<Box<dyn std::io::Write + Send>>,
-    }
-    impl Target {
-        fn new() -> Self {
-            Self {
-                inner: Mutex::new(Box::new(std::io::stderr())),
+/// Mark a reference point: print a check with a zero delta, the delta of the next [check] is counted from here
+///
+/// Unlike [start] the total time keeps running and there is no "Starting" line
+#[macro_export]
+macro_rules! zero {
+    () => {
+        $crate::zero!($crate::__default_label!());
+    };
+    ($msg: expr) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
             }
-        }
-        #[doc(hidden)]
-        pub fn get(&self) -> MutexGuard<Box<dyn std::io::Write + Send>> {
-            self.inner.lock().unwrap()
-        }
-        /// Set the output target of logging functions
-        pub fn set(&mut self, target: impl std::io::Write + Send + 'static) {
-            *self.get() = Box::new(target);
-        }
-        /// Reset the output target of logging functions to stderr
-        pub fn reset(&self) {
-            *self.get() = Box::new(std::io::stderr());
-        }
-    }
+            let mut opts = $crate::CheckOpts::new(concat!(file!(), ":", line!()));
+            opts.zero = true;
+            $crate::checkpoint(&$msg, opts)
+        });
+    };
+}
 
-    /// Time a block of code
-    ///
-    /// The timer starts immediately when this function is called
-    ///
-    /// Its ends when the guard it returns is dropped
-    pub fn time_it(msg: &'static str) -> impl Drop {
-        struct TimeIt {
-            msg: &'static str,
-            start: Instant,
-        }
-        impl Drop for TimeIt {
-            fn drop(&mut self) {
-                let end = Instant::now();
-                let dur = end.duration_since(self.start);
-                let _ = writeln!(
-                    OUTPUT_TARGET.get(),
-                    "{}: {}",
-                    self.msg.yellow().italic(),
-                    FDur(dur)
-                );
+/// Set a named mark at the current time, that `check!(since_global = "name", ..)` measures its total time from
+///
+/// Marks are global and are never cleared (a [start] doesn't touch them), so they can anchor measurements across sessions,
+/// example: `global_mark!("boot")` at the beginning of `main`. Setting a mark again moves it
+#[macro_export]
+macro_rules! global_mark {
+    ($name: expr) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
             }
-        }
-        TimeIt {
-            start: Instant::now(),
-            msg,
-        }
-    }
+            $crate::advanced::global_mark($name);
+            Ok(())
+        });
+    };
+}
 
-    /// Convenient custom log wrapper
-    ///
-    /// It wraps an Arc so it can be cloned freely
-    #[derive(Default)]
-    pub struct CustomLog<W> {
-        log: Arc<Mutex<W>>,
-    }
-    impl<W> Clone for CustomLog<W> {
-        fn clone(&self) -> Self {
-            Self {
-                log: self.log.clone(),
+/// Check and print the throughput of the delta, for `count` items named `items`
+///
+/// Example: `check_rate!("rows", 100_000, "processed")` prints `processed (100000 rows / 50 ms = 2.0M rows/s)` after the durations
+///
+/// `count` can be any integer or float
+#[macro_export]
+macro_rules! check_rate {
+    ($items: expr, $count: expr) => {
+        $crate::check_rate!($items, $count, concat!(file!(), ":", line!()));
+    };
+    ($items: expr, $count: expr, $msg: expr) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
             }
-        }
-    }
-    impl<W> CustomLog<W> {
-        fn lock(&self) -> MutexGuard<W> {
-            self.log.lock().unwrap()
-        }
-    }
-    impl<W: io::Read> CustomLog<W> {
-        /// Create a CustomLog from a custom type
-        pub fn new(log: W) -> Self {
-            Self {
-                log: Arc::new(Mutex::new(log)),
+            let mut opts = $crate::CheckOpts::new(concat!(file!(), ":", line!()));
+            opts.rate = Some(($items, $count as f64));
+            $crate::checkpoint(&$msg, opts)
+        });
+    };
+}
+
+/// Panic if the ratio of the deltas of two recorded phases is outside of a range
+///
+/// Meant for balance checks in tests (recording must be enabled, see [advanced::set_recording]),
+/// example: `assert_ratio!("encode", "decode", 0.8..1.2)`
+#[macro_export]
+macro_rules! assert_ratio {
+    ($a: expr, $b: expr, $range: expr) => {
+        $crate::advanced::assert_ratio($a, $b, $range)
+    };
+}
+
+/// Start a [advanced::Progress] for a long operation expected to take `expected`
+///
+/// Each [advanced::Progress::tick] prints how far along it is based on the elapsed time, example:
+/// `loading [==========          ] 50% (5 s/10 s)`, and when it is dropped the actual total is printed
+/// (in red if it took longer than expected)
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// let mut progress = stime::progress!("loading", Duration::from_secs(10)).inline();
+/// for _chunk in 0..10 {
+///     progress.tick();
+/// }
+/// ```
+#[macro_export]
+macro_rules! progress {
+    ($msg: expr, $expected: expr) => {
+        $crate::advanced::progress($msg, $expected)
+    };
+}
+
+/// Time one level of a recursive function, the line printed when the guard is dropped includes the recursion depth
+///
+/// `let _depth = stime::recursion!("fib");` at the top of `fib` prints `fib [depth 2]: 3 us` for each call,
+/// nested calls are scopes of their parent like with [advanced::time_it] (so their self time is shown).
+/// The calls and time of each depth are also aggregated, [advanced::print_recursion_summary] prints them
+#[macro_export]
+macro_rules! recursion {
+    ($msg: expr) => {
+        $crate::advanced::recursion($msg)
+    };
+}
+
+/// Start a named span, it is ended (and its duration printed) by the [span_end] with the same name
+///
+/// Unlike [advanced::time_it] spans are not tied to a scope, they can start and end anywhere, even on different threads
+///
+/// Starting a span that is already pending restarts it
+#[macro_export]
+macro_rules! span_start {
+    ($name: expr) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
             }
-        }
-        /// Read The log
-        pub fn read(&self) -> io::Result<String> {
-            let mut s = String::new();
-            self.lock().read_to_string(&mut s)?;
-            Ok(s)
-        }
-    }
-    impl<W: io::Write> io::Write for CustomLog<W> {
-        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-            self.lock().write(buf)
-        }
-        fn flush(&mut self) -> std::io::Result<()> {
-            self.lock().flush()
-        }
-    }
+            $crate::span_start($name);
+            Ok(())
+        });
+    };
 }
 
-#[doc(hidden)]
-pub struct FDur(pub std::time::Duration);
-impl std::fmt::Display for FDur {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        if self.0.as_secs() != 0 {
-            write!(f, "{} {}", self.0.as_secs().red(), "s".red())
-        } else if self.0.as_millis() != 0 {
-            write!(f, "{} {}", self.0.as_millis().yellow(), "ms".yellow())
-        } else if self.0.as_micros() != 0 {
-            write!(f, "{} {}", self.0.as_micros().green(), "us".green())
-        } else {
-            write!(
-                f,
-                "{} {}",
-                self.0.as_nanos().rgb_fg(255, 255, 255),
-                "ns".rgb_fg(255, 255, 255)
-            )
+/// End the span started by the [span_start] with the same name and print its duration
+///
+/// The duration is also kept as a [advanced::Record] when recording is enabled
+#[macro_export]
+macro_rules! span_end {
+    ($name: expr) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            if $crate::listed(concat!(file!(), ":", line!()), &$name)? {
+                return Ok(());
+            }
+            $crate::span_end($name)
+        });
+    };
+}
+
+/// Time the initializer of a variable and bind it, the variable name is the label, like [dbg] but for timing
+///
+/// Example: `timed_var!(rows = load_rows());` binds `rows` and prints `rows: 12 ms`, `timed_var!(mut rows = ..)` binds a mutable variable
+///
+/// When stime is inactive it only binds the variable
+#[macro_export]
+macro_rules! timed_var {
+    (mut $name: ident = $e: expr) => {
+        let mut $name = $crate::timed_var!(@time $name, $e);
+    };
+    ($name: ident = $e: expr) => {
+        let $name = $crate::timed_var!(@time $name, $e);
+    };
+    (@time $name: ident, $e: expr) => {{
+        let start = ::std::time::Instant::now();
+        let value = $e;
+        let elapsed_time = start.elapsed();
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            if $crate::listed(concat!(file!(), ":", line!()), &stringify!($name))? {
+                return Ok(());
+            }
+            $crate::print_timed(&stringify!($name), elapsed_time, true)
+        });
+        value
+    }};
+}
+
+/// Prints the elapsed time since the last call to [start] (and the delta between checks)
+///
+/// If [start] was not called yet it will print the elapsed time since stime was first used (with a one-time warning)
+///
+/// `check` can accept an optional message that implement [std::fmt::Display] to show, if no message is given it will display `file_name:call_line` instead
+///
+/// Options can be given before the message:
+/// - `unit = s|ms|us|ns|debug|auto` (or the name of a unit registered with [advanced::add_unit]) forces the unit of the printed durations for this call, example: `check!(unit = ms, "msg")`
+/// - `tag = "io"` tags the check (shown before the message and kept in the [advanced::Record]), example: `check!(tag = "db", "query")`, see [advanced::set_tag_filter] to only print some tags
+/// - `at = instant` measures the total time from the given [std::time::Instant] instead of the last [start] (the delta is still since the previous check),
+///   example: `check!(at = request.received, "handled")`
+/// - `since_global = "boot"` measures the total time from the [global_mark] with that name (from the last [start] if there is none)
+/// - `level = 2` only prints the check when the level is at least 2 (see [advanced::set_level]), checks without it have level 1
+///
+/// Fields can be given after the message, their values implement [std::fmt::Display]: `check!("loaded", rows = 100, bytes = 4096)`,
+/// they are printed after the message as `rows=100 bytes=4096` (as fields of the object in JSON) and kept in the [advanced::Record]
+///
+/// @modifier writes only this line to the given writer instead of the output target: `check!(@std::io::stdout(), "msg")`,
+/// the options, message and fields can follow it
+#[macro_export]
+macro_rules! check {
+    (@$target: expr $(, $($args: tt)*)?) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            let mut opts = $crate::CheckOpts::new(concat!(file!(), ":", line!()));
+            $crate::redirect(&mut $target, move || $crate::__check!(opts; $($($args)*)?))
+        });
+    };
+    ($($args: tt)*) => {
+        $crate::rtry!({
+            if !$crate::active() {
+                return Ok(());
+            }
+            let mut opts = $crate::CheckOpts::new(concat!(file!(), ":", line!()));
+            $crate::__check!(opts; $($args)*)
+        });
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __check {
+    ($opts: ident; unit = $unit: ident $(, $($rest: tt)*)?) => {{
+        $opts.unit = Some($crate::__unit!($unit));
+        $crate::__check!($opts; $($($rest)*)?)
+    }};
+    ($opts: ident; tag = $tag: expr $(, $($rest: tt)*)?) => {{
+        $opts.tag = Some($tag);
+        $crate::__check!($opts; $($($rest)*)?)
+    }};
+    ($opts: ident; level = $level: expr $(, $($rest: tt)*)?) => {{
+        $opts.level = $level;
+        $crate::__check!($opts; $($($rest)*)?)
+    }};
+    ($opts: ident; at = $at: expr $(, $($rest: tt)*)?) => {{
+        $opts.at = Some($at);
+        $crate::__check!($opts; $($($rest)*)?)
+    }};
+    ($opts: ident; since_global = $mark: expr $(, $($rest: tt)*)?) => {{
+        $opts.at = $crate::advanced::global_mark_instant($mark);
+        $crate::__check!($opts; $($($rest)*)?)
+    }};
+    ($opts: ident;) => {
+        $crate::checkpoint(&$crate::__default_label!(), $opts)
+    };
+    ($opts: ident; $msg: expr) => {
+        $crate::checkpoint(&$msg, $opts)
+    };
+    ($opts: ident; $msg: expr, $($key: ident = $value: expr),+ $(,)?) => {{
+        $($opts.fields.push((stringify!($key), $value.to_string()));)+
+        $crate::checkpoint(&$msg, $opts)
+    }};
+}
+
+/// The label of a call site without a message: `file:line`, followed by the function name with [advanced::set_label_function]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __default_label {
+    () => {
+        $crate::default_label(concat!(file!(), ":", line!()), {
+            fn f() {}
+            ::std::any::type_name_of_val(&f)
+        })
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __unit {
+    (s) => {
+        $crate::advanced::Unit::S
+    };
+    (ms) => {
+        $crate::advanced::Unit::Ms
+    };
+    (us) => {
+        $crate::advanced::Unit::Us
+    };
+    (ns) => {
+        $crate::advanced::Unit::Ns
+    };
+    (auto) => {
+        $crate::advanced::Unit::Auto
+    };
+    (debug) => {
+        $crate::advanced::Unit::RustDebug
+    };
+    ($custom: ident) => {
+        $crate::advanced::Unit::Custom(stringify!($custom))
+    };
+}
+
+/// Convenient utilities for advanced use-cases
+///
+/// Like the macros, everything that measures or prints is a cheap no-op while stime is inactive: guards
+/// ([advanced::time_it], [advanced::run], [advanced::progress]..) don't read the clock nor print, [advanced::TimerToken]s
+/// measure zero and [advanced::write_line] writes nothing. Settings (`set_*`, [advanced::configure]..) still apply,
+/// so they are in place if stime is activated later
+pub mod advanced {
+    pub use crate::format::read_binary_log;
+    #[cfg(feature = "gzip")]
+    pub use crate::gzip::GzipTarget;
+    pub use crate::record::{
+        aggregate, assert_phases, assert_ratio, assert_within, clear_hooks, drain_records,
+        fold_records, merged_report, on_check, on_start, print_aggregate, records, report,
+        set_recording, set_report_interval, to_dot, write_prometheus, Aggregate, Hook, Record,
+        RunReport,
+    };
+
+    use crate::{format::JsonLine, FDur};
+    use once_cell::sync::Lazy;
+    use scolor::ColorExt;
+    use std::{
+        borrow::Cow,
+        cell::RefCell,
+        io,
+        sync::{
+            atomic::{AtomicBool, AtomicU32, Ordering},
+            mpsc, Arc, Mutex, MutexGuard, RwLock,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// The output target of all logging functions, it defaults to stderr
+    pub static OUTPUT_TARGET: Lazy<Target> = Lazy::new(Target::new);
+
+    /// The output target of all logging functions, it defaults to stderr
+    pub struct Target {
+        inner: Mutex<Box<dyn std::io::Write + Send>>,
+        // the target replaced by `start_to`, put back by the next `start`
+        previous: Mutex<Option<(Box<dyn std::io::Write + Send>, HeaderState)>>,
+        header: Mutex<HeaderState>,
+    }
+    /// If the target is a file, and if it is still waiting for its header, see [set_header]
+    #[derive(Clone, Copy)]
+    struct HeaderState {
+        file: bool,
+        pending: bool,
+    }
+    impl HeaderState {
+        fn of(target: &(impl std::io::Write + 'static)) -> Self {
+            let target: &dyn std::any::Any = target;
+            let file = target.is::<std::fs::File>();
+            #[cfg(feature = "gzip")]
+            let file = file || target.is::<GzipTarget>();
+            HeaderState {
+                file,
+                pending: true,
+            }
+        }
+    }
+    impl Target {
+        fn new() -> Self {
+            Self {
+                inner: Mutex::new(Box::new(std::io::stderr())),
+                previous: Mutex::new(None),
+                header: Mutex::new(HeaderState::of(&std::io::stderr())),
+            }
+        }
+        #[doc(hidden)]
+        pub fn get(&self) -> MutexGuard<'_, Box<dyn std::io::Write + Send>> {
+            // there is only one target per copy of stime, it might have been replaced by the host's one
+            state().target.inner.lock().unwrap()
+        }
+        /// Set the output target of logging functions
+        ///
+        /// Any writer works, for example `GzipTarget` (with the `gzip` feature) to compress long sessions
+        pub fn set(&self, target: impl std::io::Write + Send + 'static) {
+            *state().target.header.lock().unwrap() = HeaderState::of(&target);
+            *self.get() = Box::new(target);
+        }
+        /// Reset the output target of logging functions to stderr
+        pub fn reset(&self) {
+            self.set(std::io::stderr());
+        }
+        #[doc(hidden)]
+        pub fn set_scoped(&self, target: impl std::io::Write + Send + 'static) {
+            let header = std::mem::replace(
+                &mut *state().target.header.lock().unwrap(),
+                HeaderState::of(&target),
+            );
+            let previous = std::mem::replace(&mut *self.get(), Box::new(target));
+            *state().target.previous.lock().unwrap() = Some((previous, header));
+        }
+        pub(crate) fn restore_scoped(&self) {
+            if let Some((previous, header)) = state().target.previous.lock().unwrap().take() {
+                *self.get() = previous;
+                *state().target.header.lock().unwrap() = header;
+            }
+        }
+        /// Replace the target and its header state, returning the previous ones
+        fn replace(
+            &self,
+            target: Box<dyn std::io::Write + Send>,
+            header: HeaderState,
+        ) -> (Box<dyn std::io::Write + Send>, HeaderState) {
+            let header = std::mem::replace(&mut *state().target.header.lock().unwrap(), header);
+            (std::mem::replace(&mut *self.get(), target), header)
+        }
+        /// Write the header if it is enabled for this target and was not written yet
+        fn write_header(&self, target: &mut dyn std::io::Write) -> io::Result<()> {
+            let mut header = state().target.header.lock().unwrap();
+            if !header.pending || !HEADER.lock().unwrap().unwrap_or(header.file) {
+                return Ok(());
+            }
+            header.pending = false;
+            writeln!(target, "{}", crate::format::header())
+        }
+    }
+
+    // the target replaced by `mute`
+    static MUTED: Mutex<Option<(Box<dyn std::io::Write + Send>, HeaderState)>> = Mutex::new(None);
+
+    /// Discard the output until [unmute], the lines are still formatted but written to [std::io::sink]
+    ///
+    /// Comparing a run with and without it measures the cost of writing the output
+    pub fn mute() {
+        let mut muted = MUTED.lock().unwrap();
+        if muted.is_none() {
+            let sink = HeaderState {
+                file: false,
+                pending: false,
+            };
+            *muted = Some(OUTPUT_TARGET.replace(Box::new(io::sink()), sink));
+        }
+    }
+    /// Put back the output target replaced by [mute]
+    pub fn unmute() {
+        if let Some((target, header)) = MUTED.lock().unwrap().take() {
+            OUTPUT_TARGET.replace(target, header);
+        }
+    }
+
+    pub(crate) static HEADER: Mutex<Option<bool>> = Mutex::new(None);
+
+    /// Write a line identifying the process (binary name, pid and start time) before the first line written to each output target,
+    /// so a log shared by several programs (or rotated) tells where its lines come from
+    ///
+    /// By default it is only written to [std::fs::File] targets
+    pub fn set_header(enabled: bool) {
+        *HEADER.lock().unwrap() = Some(enabled);
+    }
+
+    pub(crate) enum AsyncMessage {
+        Line(String),
+        // answered once the lines sent before it are written
+        Drain(mpsc::Sender<()>),
+    }
+
+    pub(crate) struct AsyncOutput {
+        pub(crate) sender: mpsc::Sender<AsyncMessage>,
+        handle: thread::JoinHandle<()>,
+    }
+
+    pub(crate) static ASYNC_OUTPUT: Lazy<Mutex<Option<AsyncOutput>>> =
+        Lazy::new(|| Mutex::new(None));
+
+    /// Returned by [set_async_output], dropping it disables the async output (which writes the pending lines)
+    #[must_use = "dropping the guard disables the async output right away"]
+    pub struct AsyncOutputGuard {
+        enabled: bool,
+    }
+    impl Drop for AsyncOutputGuard {
+        fn drop(&mut self) {
+            if self.enabled {
+                stop_async_output();
+            }
+        }
+    }
+
+    /// Write the output from a dedicated thread, so the measured thread only pays for a channel send
+    ///
+    /// The writer thread writes to [OUTPUT_TARGET] at the time it receives a line
+    ///
+    /// Keep the returned guard for as long as the async output should last, example: `let _output = set_async_output(true);` in `main`,
+    /// dropping it (or disabling the async output) waits for all pending lines to be written so no output is lost at exit.
+    /// [run] guards and [install_panic_reporter] also wait for the pending lines
+    pub fn set_async_output(enabled: bool) -> AsyncOutputGuard {
+        if !enabled {
+            stop_async_output();
+            return AsyncOutputGuard { enabled };
+        }
+        let mut output = ASYNC_OUTPUT.lock().unwrap();
+        if output.is_none() {
+            let (sender, receiver) = mpsc::channel();
+            let handle = thread::spawn(move || {
+                for message in receiver {
+                    match message {
+                        AsyncMessage::Line(line) => {
+                            let _ = write_out(&line);
+                        }
+                        AsyncMessage::Drain(done) => {
+                            let _ = OUTPUT_TARGET.get().flush();
+                            let _ = done.send(());
+                        }
+                    }
+                }
+                let _ = OUTPUT_TARGET.get().flush();
+            });
+            *output = Some(AsyncOutput { sender, handle });
+        }
+        AsyncOutputGuard { enabled }
+    }
+
+    fn stop_async_output() {
+        let output = ASYNC_OUTPUT.lock().unwrap().take();
+        if let Some(AsyncOutput { sender, handle }) = output {
+            drop(sender);
+            let _ = handle.join();
+        }
+    }
+
+    /// Wait (at most `timeout`) for the lines queued by the async output to be written
+    pub(crate) fn drain_async_output(timeout: Duration) {
+        // a panic can happen while the lock is held
+        let output = match thread::panicking() {
+            true => ASYNC_OUTPUT.try_lock().ok(),
+            false => ASYNC_OUTPUT.lock().ok(),
+        };
+        let Some(sender) = output.and_then(|output| Some(output.as_ref()?.sender.clone())) else {
+            return;
+        };
+        let (done, drained) = mpsc::channel();
+        if sender.send(AsyncMessage::Drain(done)).is_ok() {
+            let _ = drained.recv_timeout(timeout);
+        }
+    }
+
+    /// A callback receiving each formatted line, see [set_line_sink]
+    pub type LineSink = Box<dyn FnMut(&str) + Send>;
+
+    static LINE_SINK: Lazy<Mutex<Option<LineSink>>> = Lazy::new(|| Mutex::new(None));
+
+    /// Route every formatted line (without the trailing newline) to `sink` instead of [OUTPUT_TARGET]
+    ///
+    /// Useful for sinks that are not byte streams, like a GUI log widget or the browser console under wasm,
+    /// where stderr goes nowhere (colors should be disabled there, see [set_color]):
+    ///
+    /// ```rust,ignore
+    /// set_color(false);
+    /// set_line_sink(Box::new(|line| web_sys::console::log_1(&line.into())));
+    /// ```
+    pub fn set_line_sink(sink: LineSink) {
+        *LINE_SINK.lock().unwrap() = Some(sink);
+    }
+    /// Go back to writing to [OUTPUT_TARGET]
+    pub fn clear_line_sink() {
+        *LINE_SINK.lock().unwrap() = None;
+    }
+
+    /// Write one line of your own to the output, going through the same path as stime lines (session, color, sinks..)
+    ///
+    /// example: `write_line(format_args!("loaded {} rows", n))`
+    pub fn write_line(args: std::fmt::Arguments) -> io::Result<()> {
+        if !crate::active() {
+            return Ok(());
+        }
+        broadcast(|| {
+            if format() == Format::Json {
+                return crate::output(JsonLine::new("line").str("msg", &args).finish());
+            }
+            crate::emit(args)
+        })
+    }
+
+    pub(crate) fn write_out(line: &str) -> io::Result<()> {
+        if let Some(sink) = &mut *LINE_SINK.lock().unwrap() {
+            sink(line);
+            return Ok(());
+        }
+        let mut target = OUTPUT_TARGET.get();
+        OUTPUT_TARGET.write_header(&mut *target)?;
+        writeln!(target, "{}", line)
+    }
+
+    /// Handle to the timer state (timer, delta baseline and output target) of a copy of stime, see [init_from_host]
+    pub struct SharedState {
+        pub(crate) chrono: &'static Mutex<Instant>,
+        pub(crate) last_duration: &'static Mutex<Option<Duration>>,
+        pub(crate) started: &'static AtomicBool,
+        target: &'static Target,
+    }
+
+    static LOCAL_STATE: Lazy<SharedState> = Lazy::new(|| SharedState {
+        chrono: &crate::CHRONO,
+        last_duration: &crate::LAST_DURATION,
+        started: &crate::STARTED,
+        target: &OUTPUT_TARGET,
+    });
+
+    static HOST_STATE: once_cell::sync::OnceCell<&'static SharedState> =
+        once_cell::sync::OnceCell::new();
+
+    pub(crate) fn state() -> &'static SharedState {
+        HOST_STATE.get().copied().unwrap_or(&LOCAL_STATE)
+    }
+
+    /// The timer state currently used by this copy of stime
+    ///
+    /// A host binary can hand it to its dynamically loaded plugins, see [init_from_host]
+    pub fn shared_state() -> &'static SharedState {
+        state()
+    }
+
+    /// Make this copy of stime use the timer state of another copy
+    ///
+    /// When stime is linked in both a host binary and a plugin, each of them gets its own statics,
+    /// the plugin can call this with the host's [shared_state] so they share the same timer and output target
+    ///
+    /// Both copies need to be the same stime version built by the same compiler, other settings (color, formatter..) are not shared
+    ///
+    /// Returns false if a host state was already adopted
+    pub fn init_from_host(host: &'static SharedState) -> bool {
+        HOST_STATE.set(host).is_ok()
+    }
+
+    static GLOBAL_MARKS: Lazy<Mutex<std::collections::HashMap<&'static str, Instant>>> =
+        Lazy::new(Default::default);
+
+    /// Set the global mark `name` at the current time, see [crate::global_mark]
+    pub fn global_mark(name: &'static str) {
+        GLOBAL_MARKS.lock().unwrap().insert(name, Instant::now());
+    }
+
+    /// When the global mark `name` was set, see [crate::global_mark]
+    pub fn global_mark_instant(name: &str) -> Option<Instant> {
+        GLOBAL_MARKS.lock().unwrap().get(name).copied()
+    }
+
+    /// The durations segment of a [crate::check] line, example: `[TotalTime: 12 ms / DeltaTime: 3 ms]`
+    ///
+    /// It follows the settings (unit, colors, split mode..) like the lines of stime, to compose stime styled timings in your own lines
+    pub fn format_check(total: Duration, delta: Duration) -> String {
+        let unit = crate::check_unit(None, total, delta);
+        crate::recolor(crate::times(
+            total,
+            FDur(delta, unit).to_string(),
+            None,
+            unit,
+        ))
+    }
+
+    /// A saved state of the global timer (its start and delta baseline), see [snapshot]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TimerSnapshot {
+        chrono: Instant,
+        last_duration: Option<Duration>,
+        started: bool,
+    }
+
+    /// Save the state of the global timer, [restore] puts it back
+    ///
+    /// Useful to time a sub-operation with its own [crate::start] and checks, then continue the outer timing
+    /// as if they never happened
+    pub fn snapshot() -> TimerSnapshot {
+        let state = state();
+        TimerSnapshot {
+            chrono: *state.chrono.lock().unwrap(),
+            last_duration: *state.last_duration.lock().unwrap(),
+            started: state.started.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Put back a state of the global timer saved with [snapshot], the time spent since then still counts
+    pub fn restore(snapshot: TimerSnapshot) {
+        let state = state();
+        *state.chrono.lock().unwrap() = snapshot.chrono;
+        *state.last_duration.lock().unwrap() = snapshot.last_duration;
+        state.started.store(snapshot.started, Ordering::Relaxed);
+    }
+
+    thread_local! {
+        pub(crate) static SESSION: RefCell<Option<String>> = const { RefCell::new(None) };
+    }
+
+    /// Tag every line printed from the current thread with `[id]`
+    ///
+    /// Useful to tell apart the output of concurrent requests, the id is usually set at request entry
+    pub fn set_session(id: impl std::fmt::Display) {
+        SESSION.with(|s| *s.borrow_mut() = Some(id.to_string()));
+    }
+    /// Stop tagging the lines printed from the current thread
+    pub fn clear_session() {
+        SESSION.with(|s| *s.borrow_mut() = None);
+    }
+
+    /// Parse the environment variable `name`, `None` if it isn't set or is invalid
+    fn env_var<T>(name: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+        parse(
+            std::env::var(name)
+                .ok()?
+                .trim()
+                .to_ascii_lowercase()
+                .as_str(),
+        )
+    }
+
+    // initialized from `STIME_COLOR` (0/1, false/true, off/on, no/yes)
+    // on Windows it is disabled by default if the console doesn't support escape sequences
+    pub(crate) static COLOR: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(default_color()));
+    fn default_color() -> bool {
+        let color = env_var("STIME_COLOR", |value| match value {
+            "0" | "false" | "off" | "no" => Some(false),
+            "1" | "true" | "on" | "yes" => Some(true),
+            _ => None,
+        });
+        // escape sequences are enabled even if STIME_COLOR is set, so it can turn colors on
+        #[cfg(windows)]
+        let color = {
+            let enabled = crate::sys::enable_virtual_terminal();
+            color.or(enabled.filter(|enabled| !enabled))
+        };
+        color.unwrap_or(true)
+    }
+
+    /// Enable or disable colored output, it is enabled by default
+    pub fn set_color(enabled: bool) {
+        COLOR.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Run `f` with colored output disabled, the previous setting is restored afterwards (even if `f` panics)
+    pub fn without_color<R>(f: impl FnOnce() -> R) -> R {
+        struct Restore(bool);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                set_color(self.0);
+            }
+        }
+        let _restore = Restore(COLOR.swap(false, Ordering::Relaxed));
+        f()
+    }
+
+    /// The colors used for colored output, see [set_palette]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Palette {
+        /// The 16 basic terminal colors
+        Ansi16,
+        /// The 256 colors palette
+        Ansi256,
+        /// 24 bit colors
+        TrueColor,
+    }
+    impl Palette {
+        /// Guess the palette supported by the terminal from the `COLORTERM` and `TERM` environment variables
+        pub fn detect() -> Self {
+            let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+            let term = std::env::var("TERM").unwrap_or_default();
+            if colorterm == "truecolor" || colorterm == "24bit" {
+                Palette::TrueColor
+            } else if term.contains("256color") {
+                Palette::Ansi256
+            } else {
+                Palette::Ansi16
+            }
+        }
+    }
+
+    pub(crate) static PALETTE: Lazy<Mutex<Palette>> = Lazy::new(|| Mutex::new(Palette::detect()));
+
+    /// Set the palette colors are mapped to, it defaults to [Palette::detect]
+    pub fn set_palette(palette: Palette) {
+        *PALETTE.lock().unwrap() = palette;
+    }
+
+    // a numeric `STIME` is the level, any other value (and `0`, which only enables stime) means 1
+    static LEVEL: Lazy<AtomicU32> = Lazy::new(|| AtomicU32::new(default_level()));
+    fn default_level() -> u32 {
+        std::env::var("STIME")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    // initialized from `STIME_TAGS` (comma separated tags)
+    pub(crate) static TAG_FILTER: Lazy<RwLock<Option<Vec<String>>>> =
+        Lazy::new(|| RwLock::new(default_tag_filter()));
+    fn default_tag_filter() -> Option<Vec<String>> {
+        std::env::var("STIME_TAGS").ok().map(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect()
+        })
+    }
+
+    /// Only print the checks tagged with one of `tags` (see the `tag` option of [crate::check]), untagged checks are still printed
+    ///
+    /// `None` prints all the checks (the default), it is initialized from `STIME_TAGS` (`STIME_TAGS=io,db`)
+    pub fn set_tag_filter(tags: Option<&[&str]>) {
+        *TAG_FILTER.write().unwrap() =
+            tags.map(|tags| tags.iter().map(|tag| tag.to_string()).collect());
+    }
+
+    /// If a check with the given tag passes the tag filter, see [set_tag_filter]
+    pub(crate) fn tag_allowed(tag: Option<&str>) -> bool {
+        match (tag, &*TAG_FILTER.read().unwrap()) {
+            (Some(tag), Some(tags)) => tags.iter().any(|allowed| allowed == tag),
+            _ => true,
+        }
+    }
+
+    /// The current level, checks with a higher `level` are not printed
+    ///
+    /// It is initialized from `STIME` when it is a number of at least 1 (`STIME=3`), and is 1 otherwise
+    pub fn level() -> u32 {
+        LEVEL.load(Ordering::Relaxed)
+    }
+
+    /// Set the level, see [level]
+    pub fn set_level(level: u32) {
+        LEVEL.store(level, Ordering::Relaxed);
+    }
+
+    /// Run `f` with the level set to `level`, the previous level is restored afterwards (even if `f` panics)
+    ///
+    /// Useful to print the detailed checks of one function only, it does not activate stime
+    pub fn with_level<R>(level: u32, f: impl FnOnce() -> R) -> R {
+        struct Restore(u32);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                set_level(self.0);
+            }
+        }
+        let _restore = Restore(LEVEL.swap(level, Ordering::Relaxed));
+        f()
+    }
+
+    /// Which parts of the output are colored, see [set_color_targets]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ColorTargets {
+        /// The durations and other measured values
+        pub values: bool,
+        /// Everything else: labels like "TotalTime:", brackets, messages..
+        pub labels: bool,
+    }
+    impl Default for ColorTargets {
+        fn default() -> Self {
+            Self {
+                values: true,
+                labels: true,
+            }
+        }
+    }
+
+    pub(crate) static COLOR_TARGETS: Mutex<ColorTargets> = Mutex::new(ColorTargets {
+        values: true,
+        labels: true,
+    });
+
+    /// Only color some parts of the output, example: plain numbers with colored labels
+    /// `set_color_targets(ColorTargets { values: false, labels: true })`
+    ///
+    /// It refines [set_color], which still disables all colors
+    pub fn set_color_targets(targets: ColorTargets) {
+        *COLOR_TARGETS.lock().unwrap() = targets;
+    }
+
+    pub(crate) static TIMER_NAME: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+    /// Name the global timer, the name is shown in the "Starting" lines until it is changed or cleared
+    pub fn set_timer_name(name: impl std::fmt::Display) {
+        *TIMER_NAME.lock().unwrap() = Some(name.to_string());
+    }
+    /// Remove the name of the global timer
+    pub fn clear_timer_name() {
+        *TIMER_NAME.lock().unwrap() = None;
+    }
+
+    pub(crate) static SESSION_SEPARATOR: Lazy<Mutex<Option<String>>> =
+        Lazy::new(|| Mutex::new(None));
+
+    /// Print `separator` on its own line before every "Starting" line except the first one, to break sessions apart,
+    /// example: `set_session_separator("────────")`
+    ///
+    /// It is not printed in [Format::Json]
+    pub fn set_session_separator(separator: impl std::fmt::Display) {
+        *SESSION_SEPARATOR.lock().unwrap() = Some(separator.to_string());
+    }
+    /// Stop printing a separator between sessions
+    pub fn clear_session_separator() {
+        *SESSION_SEPARATOR.lock().unwrap() = None;
+    }
+
+    pub(crate) static LABEL_FUNCTION: AtomicBool = AtomicBool::new(false);
+
+    /// Add the name of the enclosing function to the `file:line` label of a [crate::start] or [crate::check] without a message,
+    /// example: `src/main.rs:12 (app::load)`
+    pub fn set_label_function(enabled: bool) {
+        LABEL_FUNCTION.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) static AUTOSTART: AtomicBool = AtomicBool::new(false);
+
+    /// Make the first [crate::check] without a prior [crate::start] start the timer itself
+    ///
+    /// Its delta is then zero and the following checks are relative to it, instead of the default "since init" (with a warning)
+    pub fn set_autostart(enabled: bool) {
+        AUTOSTART.store(enabled, Ordering::Relaxed);
+    }
+
+    /// A time unit used to display durations
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum Unit {
+        /// Pick the biggest unit that is not zero (the default)
+        #[default]
+        Auto,
+        /// Seconds
+        S,
+        /// Milliseconds
+        Ms,
+        /// Microseconds
+        Us,
+        /// Nanoseconds
+        Ns,
+        /// Rust's own [Duration] Debug formatting, example: `1.5ms`
+        RustDebug,
+        /// A unit registered with [add_unit], by name, example: `Unit::Custom("frame")`
+        ///
+        /// A name that was not registered is shown as [Unit::Auto]
+        Custom(&'static str),
+    }
+
+    pub(crate) static CUSTOM_UNITS: RwLock<Vec<(&'static str, Duration)>> = RwLock::new(Vec::new());
+
+    /// Register a unit of the given duration, so durations can be shown in it with [Unit::Custom]
+    ///
+    /// Example: `add_unit("frame", Duration::from_nanos(16_666_667))` then durations in `Unit::Custom("frame")`
+    /// show like `3 frames` (the name gets an `s` unless the value is 1), `check!(unit = frame, "msg")` works too.
+    /// Registering a name again replaces its duration
+    pub fn add_unit(name: &'static str, duration: Duration) {
+        let mut units = CUSTOM_UNITS.write().unwrap();
+        match units.iter_mut().find(|(unit, _)| *unit == name) {
+            Some(unit) => unit.1 = duration,
+            None => units.push((name, duration)),
+        }
+    }
+
+    // initialized from `STIME_UNIT` (s, ms, us, ns, debug or auto)
+    pub(crate) static UNIT: Lazy<Mutex<Unit>> = Lazy::new(|| Mutex::new(default_unit()));
+    fn default_unit() -> Unit {
+        let unit = env_var("STIME_UNIT", |value| match value {
+            "s" => Some(Unit::S),
+            "ms" => Some(Unit::Ms),
+            "us" | "µs" => Some(Unit::Us),
+            "ns" => Some(Unit::Ns),
+            "debug" => Some(Unit::RustDebug),
+            "auto" => Some(Unit::Auto),
+            _ => None,
+        });
+        unit.unwrap_or_default()
+    }
+
+    /// Set the unit used to display durations, it can still be overridden per [crate::check] call
+    pub fn set_unit(unit: Unit) {
+        *UNIT.lock().unwrap() = unit;
+    }
+
+    /// How durations are rounded to the displayed precision, see [set_rounding]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum Round {
+        /// Round towards zero, `1999 us` shown in ms is `1 ms` (the default)
+        #[default]
+        Down,
+        /// Round to the nearest value, `1999 us` shown in ms is `2 ms`
+        Nearest,
+        /// Round away from zero, `1001 us` shown in ms is `2 ms`
+        Up,
+    }
+
+    pub(crate) static ROUNDING: Mutex<Round> = Mutex::new(Round::Down);
+
+    /// Set how durations are rounded when they are displayed, both to whole units and to fractions of a forced unit
+    pub fn set_rounding(round: Round) {
+        *ROUNDING.lock().unwrap() = round;
+    }
+
+    pub(crate) static SIGFIGS: AtomicU32 = AtomicU32::new(0);
+
+    /// Show durations with `sigfigs` significant figures whatever their magnitude, example with 3: `1.23 ms`, `45.6 us`, `789 ns`
+    ///
+    /// `0` goes back to the default (whole units, or fractions for a forced unit), see also [set_rounding]
+    pub fn set_sigfigs(sigfigs: u32) {
+        SIGFIGS.store(sigfigs, Ordering::Relaxed);
+    }
+
+    /// How durations over the alarm threshold are shown, see [set_alarm]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Style {
+        /// Bold red
+        Bold,
+        /// Blinking bold red (not all terminals blink)
+        Blink,
+        /// Bold red on an inverted background
+        Inverse,
+    }
+
+    pub(crate) static ALARM: Mutex<Option<(Duration, Style)>> = Mutex::new(None);
+
+    /// Show any duration (total, delta, scope..) longer than `threshold` with `style`, instead of its unit color,
+    /// so pathological slowness stands out in a long log
+    pub fn set_alarm(threshold: Duration, style: Style) {
+        *ALARM.lock().unwrap() = Some((threshold, style));
+    }
+    /// Stop highlighting long durations
+    pub fn clear_alarm() {
+        *ALARM.lock().unwrap() = None;
+    }
+
+    pub(crate) static COARSE: AtomicBool = AtomicBool::new(false);
+
+    /// Show durations as rough values for status logs where precision is noise: `<1ms`, `~5ms`, `~2s`, `~1m`, `~3h`
+    ///
+    /// It takes precedence over the unit, rounding and significant figures settings
+    pub fn set_coarse(enabled: bool) {
+        COARSE.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) static CLOCK_STYLE: AtomicBool = AtomicBool::new(false);
+
+    /// Show durations of a minute or more in hours, minutes and seconds, example: `1 h 2 m 10 s` instead of `3730 s`
+    ///
+    /// Shorter durations and forced units (see [set_unit]) are shown as usual
+    pub fn set_clock_style(enabled: bool) {
+        CLOCK_STYLE.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) static SPLIT_MODE: AtomicBool = AtomicBool::new(false);
+
+    /// Show each [crate::check] as its offset from the last [crate::start], like the splits of a stopwatch, example: `[+120 ms] parsed`
+    ///
+    /// The delta is not shown
+    pub fn set_split_mode(enabled: bool) {
+        SPLIT_MODE.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) static CONSISTENT_UNITS: AtomicBool = AtomicBool::new(false);
+
+    /// Print all the durations of a [crate::check] line in the same unit, the one of the biggest duration (with fractions),
+    /// so they are easy to compare, example: `[TotalTime: 1.2 s / DeltaTime: 0.3 s]`
+    ///
+    /// It has no effect when a unit is set, see [set_unit]
+    pub fn set_consistent_units(enabled: bool) {
+        CONSISTENT_UNITS.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) static COMPACT: AtomicBool = AtomicBool::new(false);
+
+    /// Print durations without a space before the unit and with `µs` instead of `us`, example: `5ms`
+    pub fn set_compact(enabled: bool) {
+        COMPACT.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) static SHOW_CPU: AtomicBool = AtomicBool::new(false);
+
+    thread_local! {
+        static LAST_CPU: std::cell::Cell<Option<Duration>> = const { std::cell::Cell::new(None) };
+    }
+
+    /// Also show the CPU time the thread spent since its previous check, next to the wall time
+    ///
+    /// A big gap between the two means the thread was waiting, only supported on Linux
+    pub fn set_show_cpu(enabled: bool) {
+        SHOW_CPU.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Take the current thread's CPU time as the baseline of its next check
+    pub(crate) fn reset_cpu() {
+        if SHOW_CPU.load(Ordering::Relaxed) {
+            LAST_CPU.with(|last| last.set(crate::sys::thread_cpu_time()));
+        }
+    }
+
+    /// The CPU time of the current thread since its previous check (or since the thread started)
+    pub(crate) fn cpu_delta() -> Option<Duration> {
+        if !SHOW_CPU.load(Ordering::Relaxed) {
+            return None;
+        }
+        let now = crate::sys::thread_cpu_time()?;
+        let last = LAST_CPU.with(|last| last.replace(Some(now)));
+        Some(now.saturating_sub(last.unwrap_or_default()))
+    }
+
+    pub(crate) static SHOW_MONO: AtomicBool = AtomicBool::new(false);
+    static PROCESS_START: Lazy<Instant> = Lazy::new(Instant::now);
+
+    /// Prefix [crate::check] lines with the monotonic time since this was first enabled, in nanoseconds, example: `@12345678ns`
+    ///
+    /// Unlike wall clock timestamps it never goes backward, so lines of several threads or logs of the same run can be ordered exactly
+    pub fn set_show_mono(enabled: bool) {
+        Lazy::force(&PROCESS_START);
+        SHOW_MONO.store(enabled, Ordering::Relaxed);
+    }
+
+    /// The monotonic time of a check, if it is shown
+    pub(crate) fn mono() -> Option<Duration> {
+        SHOW_MONO
+            .load(Ordering::Relaxed)
+            .then(|| PROCESS_START.elapsed())
+    }
+
+    pub(crate) static SHOW_RSS: AtomicBool = AtomicBool::new(false);
+    static LAST_RSS: Mutex<Option<u64>> = Mutex::new(None);
+
+    /// Also show how much the resident set size of the process changed since the previous check, as a signed byte count
+    ///
+    /// Only supported on Linux
+    pub fn set_show_rss(enabled: bool) {
+        SHOW_RSS.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Take the current resident set size as the baseline of the next check
+    pub(crate) fn reset_rss() {
+        if SHOW_RSS.load(Ordering::Relaxed) {
+            *LAST_RSS.lock().unwrap() = crate::sys::rss();
+        }
+    }
+
+    /// The change of the resident set size since the previous check (or since the first measurement)
+    pub(crate) fn rss_delta() -> Option<i64> {
+        if !SHOW_RSS.load(Ordering::Relaxed) {
+            return None;
+        }
+        let now = crate::sys::rss()?;
+        let last = LAST_RSS.lock().unwrap().replace(now);
+        Some(now as i64 - last.unwrap_or(now) as i64)
+    }
+
+    struct RateLimit {
+        lines_per_sec: u32,
+        last: Option<Instant>,
+        suppressed: u64,
+    }
+
+    static RATE_LIMIT: Mutex<Option<RateLimit>> = Mutex::new(None);
+
+    /// Print at most `lines_per_sec` check lines per second, the others are dropped (they still advance the delta baseline)
+    ///
+    /// The number of dropped lines is printed before the next printed one, `0` removes the limit
+    pub fn set_rate_limit(lines_per_sec: u32) {
+        *RATE_LIMIT.lock().unwrap() = (lines_per_sec != 0).then_some(RateLimit {
+            lines_per_sec,
+            last: None,
+            suppressed: 0,
+        });
+    }
+
+    /// Returns None if the line should be dropped, or the number of lines dropped before it
+    pub(crate) fn rate_limit() -> Option<u64> {
+        let mut limit = RATE_LIMIT.lock().unwrap();
+        let Some(limit) = &mut *limit else {
+            return Some(0);
+        };
+        let now = Instant::now();
+        let interval = Duration::from_secs(1) / limit.lines_per_sec;
+        if limit.last.is_some_and(|last| now - last < interval) {
+            limit.suppressed += 1;
+            return None;
+        }
+        limit.last = Some(now);
+        Some(std::mem::take(&mut limit.suppressed))
+    }
+
+    static EMA_ALPHA: Mutex<Option<f64>> = Mutex::new(None);
+    static EMA: Lazy<Mutex<std::collections::HashMap<&'static str, f64>>> =
+        Lazy::new(Default::default);
+
+    /// Show an exponential moving average of the delta of each [crate::check] call site next to its delta
+    ///
+    /// `alpha` (between 0 and 1) is the weight of the newest delta, `None` hides it
+    pub fn set_show_ema(alpha: Option<f64>) {
+        *EMA_ALPHA.lock().unwrap() = alpha;
+        EMA.lock().unwrap().clear();
+    }
+
+    /// Updates and returns the moving average of the call site
+    pub(crate) fn ema(site: &'static str, delta: Duration) -> Option<Duration> {
+        let alpha = (*EMA_ALPHA.lock().unwrap())?;
+        let delta = delta.as_nanos() as f64;
+        let mut ema = EMA.lock().unwrap();
+        let avg = ema
+            .entry(site)
+            .and_modify(|avg| *avg = alpha * delta + (1.0 - alpha) * *avg)
+            .or_insert(delta);
+        Some(Duration::from_nanos(*avg as u64))
+    }
+
+    static TREND_COLORING: AtomicBool = AtomicBool::new(false);
+    static LAST_DELTAS: Lazy<Mutex<std::collections::HashMap<&'static str, Duration>>> =
+        Lazy::new(Default::default);
+
+    /// Color the delta of a [crate::check] green (with ↓) if it is smaller than the previous delta of the same call site, or red (with ↑) if it is bigger
+    ///
+    /// Useful to spot steps getting slower at each iteration of a loop
+    pub fn set_trend_coloring(enabled: bool) {
+        TREND_COLORING.store(enabled, Ordering::Relaxed);
+        LAST_DELTAS.lock().unwrap().clear();
+    }
+
+    /// Compares the delta to the previous delta of the call site, and remembers it
+    pub(crate) fn trend(site: &'static str, delta: Duration) -> Option<std::cmp::Ordering> {
+        if !TREND_COLORING.load(Ordering::Relaxed) {
+            return None;
+        }
+        let previous = LAST_DELTAS.lock().unwrap().insert(site, delta)?;
+        Some(delta.cmp(&previous))
+    }
+
+    /// The output format, see [set_format]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum Format {
+        /// Colored lines for humans (the default)
+        #[default]
+        Human,
+        /// One json object per line (NDJSON), for start, check, time_it and try_timed events
+        ///
+        /// Every object carries the [run_id], durations are in nanoseconds
+        Json,
+        /// A compact binary record per check, for very high event rates, it can be decoded with [read_binary_log]
+        ///
+        /// Each record is `total: u64, delta: u64` (nanoseconds), `msg_len: u16` and the message bytes, all little endian.
+        /// Other events are not written, and the records go straight to [OUTPUT_TARGET] (without the async output or the line sink),
+        /// so they need a byte target: a file, or a [MemoryLog] read with [MemoryLog::bytes]
+        Binary,
+    }
+
+    // initialized from `STIME_FORMAT` (human, json or binary)
+    pub(crate) static FORMAT: Lazy<Mutex<Format>> = Lazy::new(|| Mutex::new(default_format()));
+    fn default_format() -> Format {
+        let format = env_var("STIME_FORMAT", |value| match value {
+            "human" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            "binary" => Some(Format::Binary),
+            _ => None,
+        });
+        format.unwrap_or_default()
+    }
+
+    /// Set the output format
+    pub fn set_format(format: Format) {
+        *FORMAT.lock().unwrap() = format;
+    }
+
+    struct Secondary {
+        target: Box<dyn io::Write + Send>,
+        format: Format,
+    }
+
+    static SECONDARY: Lazy<Mutex<Option<Secondary>>> = Lazy::new(|| Mutex::new(None));
+
+    thread_local! {
+        // set while a line is rendered for the secondary target
+        static FORMAT_OVERRIDE: std::cell::Cell<Option<Format>> = const { std::cell::Cell::new(None) };
+        static SECONDARY_LINES: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Set the main output: the [OUTPUT_TARGET] and its [Format]
+    pub fn set_primary(target: impl io::Write + Send + 'static, format: Format) {
+        OUTPUT_TARGET.set(target);
+        set_format(format);
+    }
+
+    /// Also write every line to `target`, in its own format, example: a concise [Format::Human] on stderr with
+    /// a detailed [Format::Json] file next to it
+    ///
+    /// Lines are written without colors, a line is written to both targets before the next one is
+    pub fn set_secondary(target: impl io::Write + Send + 'static, format: Format) {
+        *SECONDARY.lock().unwrap() = Some(Secondary {
+            target: Box::new(target),
+            format,
+        });
+    }
+
+    /// Stop writing to the secondary target (and drop it)
+    pub fn clear_secondary() {
+        *SECONDARY.lock().unwrap() = None;
+    }
+
+    /// The format of the line being rendered
+    pub(crate) fn format() -> Format {
+        FORMAT_OVERRIDE
+            .get()
+            .unwrap_or_else(|| *FORMAT.lock().unwrap())
+    }
+
+    /// Print with `print` to the output, then again to the secondary target if there is one
+    pub(crate) fn broadcast<E: From<io::Error>>(
+        print: impl Fn() -> Result<(), E>,
+    ) -> Result<(), E> {
+        // held for both so lines from other threads can't get in between
+        let mut secondary = SECONDARY.lock().unwrap();
+        let result = print();
+        let Some(secondary) = &mut *secondary else {
+            return result;
+        };
+        FORMAT_OVERRIDE.set(Some(secondary.format));
+        SECONDARY_LINES.with(|lines| lines.borrow_mut().clear());
+        let rendered = print();
+        FORMAT_OVERRIDE.set(None);
+        for line in SECONDARY_LINES.with(|lines| lines.take()) {
+            secondary.target.write_all(&line)?;
+        }
+        result.and(rendered)
+    }
+
+    /// Keep `line` for the secondary target if it is being rendered for it
+    pub(crate) fn capture_secondary(line: &str) -> bool {
+        let mut line = crate::strip_ansi(line);
+        line.push('\n');
+        capture_secondary_bytes(line.as_bytes())
+    }
+
+    /// Like [capture_secondary] for bytes that are kept as they are
+    pub(crate) fn capture_secondary_bytes(bytes: &[u8]) -> bool {
+        if FORMAT_OVERRIDE.get().is_none() {
+            return false;
+        }
+        SECONDARY_LINES.with(|lines| lines.borrow_mut().push(bytes.to_vec()));
+        true
+    }
+
+    /// An id of the current process run, generated on first use
+    ///
+    /// It is included in every json line so several runs appended to the same file can be told apart
+    pub fn run_id() -> &'static str {
+        static RUN_ID: Lazy<String> = Lazy::new(|| {
+            let nanos = run_start()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            format!("{:x}-{:x}", nanos, std::process::id())
+        });
+        &RUN_ID
+    }
+
+    /// When stime was first used in this process
+    pub(crate) fn run_start() -> std::time::SystemTime {
+        static RUN_START: Lazy<std::time::SystemTime> = Lazy::new(std::time::SystemTime::now);
+        *RUN_START
+    }
+
+    /// A snapshot of stime's settings, see [config] and [configure]
+    ///
+    /// Hooks and outputs (formatter, line sink, output target..) are not part of it
+    #[derive(Debug, Clone, PartialEq)]
+    #[non_exhaustive]
+    pub struct Config {
+        /// Whether the macros do anything, initialized from the `STIME` environment variable
+        pub active: bool,
+        /// See [set_format]
+        pub format: Format,
+        /// See [set_color]
+        pub color: bool,
+        /// See [set_unit]
+        pub unit: Unit,
+        /// See [set_compact]
+        pub compact: bool,
+        /// See [set_autostart]
+        pub autostart: bool,
+        /// See [set_scope_relative]
+        pub scope_relative: bool,
+        /// See [set_show_cpu]
+        pub show_cpu: bool,
+        /// See [set_rate_limit], `0` when there is no limit
+        pub rate_limit: u32,
+        /// See [set_show_ema]
+        pub ema: Option<f64>,
+        /// See [set_trend_coloring]
+        pub trend_coloring: bool,
+        /// See [set_timer_name]
+        pub timer_name: Option<String>,
+        /// See [set_clock_style]
+        pub clock_style: bool,
+        /// See [set_report_interval], zero when there are no reports
+        pub report_interval: Duration,
+        /// See [set_show_mono]
+        pub show_mono: bool,
+        /// See [calibrate], zero when not calibrated
+        pub overhead: Duration,
+        /// See [set_coarse]
+        pub coarse: bool,
+        /// See [set_milestones]
+        pub milestones: Vec<Duration>,
+        /// See [set_lock_threshold]
+        pub lock_threshold: Duration,
+        /// See [set_header], `None` only writes it to file targets
+        pub header: Option<bool>,
+        /// See [set_tag_filter]
+        pub tag_filter: Option<Vec<String>>,
+        /// See [set_split_mode]
+        pub split_mode: bool,
+        /// See [set_alarm]
+        pub alarm: Option<(Duration, Style)>,
+        /// See [set_label_function]
+        pub label_function: bool,
+        /// See [set_color_targets]
+        pub color_targets: ColorTargets,
+        /// See [set_session_separator]
+        pub session_separator: Option<String>,
+        /// See [set_sigfigs], `0` when it is disabled
+        pub sigfigs: u32,
+        /// See [set_rounding]
+        pub rounding: Round,
+        /// See [set_consistent_units]
+        pub consistent_units: bool,
+        /// See [set_level]
+        pub level: u32,
+        /// See [set_show_rss]
+        pub show_rss: bool,
+        /// See [set_palette]
+        pub palette: Palette,
+    }
+
+    /// The current settings
+    ///
+    /// Together with [configure] it can be used to save and restore the settings around a block
+    pub fn config() -> Config {
+        Config {
+            active: crate::active(),
+            format: *FORMAT.lock().unwrap(),
+            color: COLOR.load(Ordering::Relaxed),
+            unit: *UNIT.lock().unwrap(),
+            compact: COMPACT.load(Ordering::Relaxed),
+            autostart: AUTOSTART.load(Ordering::Relaxed),
+            scope_relative: SCOPE_RELATIVE.load(Ordering::Relaxed),
+            show_cpu: SHOW_CPU.load(Ordering::Relaxed),
+            rate_limit: RATE_LIMIT
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map_or(0, |limit| limit.lines_per_sec),
+            ema: *EMA_ALPHA.lock().unwrap(),
+            trend_coloring: TREND_COLORING.load(Ordering::Relaxed),
+            timer_name: TIMER_NAME.lock().unwrap().clone(),
+            clock_style: CLOCK_STYLE.load(Ordering::Relaxed),
+            report_interval: crate::record::report_interval(),
+            show_mono: SHOW_MONO.load(Ordering::Relaxed),
+            overhead: *OVERHEAD.lock().unwrap(),
+            coarse: COARSE.load(Ordering::Relaxed),
+            milestones: MILESTONES.lock().unwrap().clone(),
+            lock_threshold: *LOCK_THRESHOLD.lock().unwrap(),
+            header: *HEADER.lock().unwrap(),
+            tag_filter: TAG_FILTER.read().unwrap().clone(),
+            split_mode: SPLIT_MODE.load(Ordering::Relaxed),
+            alarm: *ALARM.lock().unwrap(),
+            label_function: LABEL_FUNCTION.load(Ordering::Relaxed),
+            color_targets: *COLOR_TARGETS.lock().unwrap(),
+            session_separator: SESSION_SEPARATOR.lock().unwrap().clone(),
+            sigfigs: SIGFIGS.load(Ordering::Relaxed),
+            rounding: *ROUNDING.lock().unwrap(),
+            consistent_units: CONSISTENT_UNITS.load(Ordering::Relaxed),
+            level: level(),
+            show_rss: SHOW_RSS.load(Ordering::Relaxed),
+            palette: *PALETTE.lock().unwrap(),
+        }
+    }
+
+    /// The settings stime starts with, including the ones given through environment variables
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                active: std::env::var("STIME").is_ok(),
+                format: default_format(),
+                color: default_color(),
+                unit: default_unit(),
+                compact: false,
+                autostart: false,
+                scope_relative: false,
+                show_cpu: false,
+                rate_limit: 0,
+                ema: None,
+                trend_coloring: false,
+                timer_name: None,
+                coarse: false,
+                milestones: Vec::new(),
+                lock_threshold: Duration::from_millis(1),
+                clock_style: false,
+                report_interval: Duration::ZERO,
+                show_mono: false,
+                overhead: Duration::ZERO,
+                header: None,
+                tag_filter: default_tag_filter(),
+                split_mode: false,
+                alarm: None,
+                label_function: false,
+                color_targets: ColorTargets {
+                    values: true,
+                    labels: true,
+                },
+                session_separator: None,
+                sigfigs: 0,
+                rounding: Round::Down,
+                consistent_units: false,
+                level: default_level(),
+                show_rss: false,
+                palette: Palette::detect(),
+            }
+        }
+    }
+
+    /// Put every setting back to its default (the environment variables are read again), see [Config::default]
+    ///
+    /// Useful to isolate tests from each other's settings, hooks and outputs are not reset
+    pub fn reset_config() {
+        configure(Config::default());
+    }
+
+    /// Apply all the settings of `config`, usually obtained from [config]
+    ///
+    /// The moving averages, trends and rate limit keep their state if their setting is unchanged
+    pub fn configure(config: Config) {
+        crate::STIME_ACTIVE.store(config.active, Ordering::Relaxed);
+        *FORMAT.lock().unwrap() = config.format;
+        COLOR.store(config.color, Ordering::Relaxed);
+        *UNIT.lock().unwrap() = config.unit;
+        COMPACT.store(config.compact, Ordering::Relaxed);
+        AUTOSTART.store(config.autostart, Ordering::Relaxed);
+        SCOPE_RELATIVE.store(config.scope_relative, Ordering::Relaxed);
+        SHOW_CPU.store(config.show_cpu, Ordering::Relaxed);
+        let rate_limit = RATE_LIMIT
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |limit| limit.lines_per_sec);
+        if rate_limit != config.rate_limit {
+            set_rate_limit(config.rate_limit);
+        }
+        if *EMA_ALPHA.lock().unwrap() != config.ema {
+            set_show_ema(config.ema);
+        }
+        if TREND_COLORING.load(Ordering::Relaxed) != config.trend_coloring {
+            set_trend_coloring(config.trend_coloring);
+        }
+        *TIMER_NAME.lock().unwrap() = config.timer_name;
+        set_clock_style(config.clock_style);
+        set_report_interval(config.report_interval);
+        set_show_mono(config.show_mono);
+        *OVERHEAD.lock().unwrap() = config.overhead;
+        set_coarse(config.coarse);
+        set_milestones(&config.milestones);
+        set_lock_threshold(config.lock_threshold);
+        *HEADER.lock().unwrap() = config.header;
+        *TAG_FILTER.write().unwrap() = config.tag_filter;
+        set_split_mode(config.split_mode);
+        *ALARM.lock().unwrap() = config.alarm;
+        set_label_function(config.label_function);
+        set_color_targets(config.color_targets);
+        *SESSION_SEPARATOR.lock().unwrap() = config.session_separator;
+        set_sigfigs(config.sigfigs);
+        set_rounding(config.rounding);
+        CONSISTENT_UNITS.store(config.consistent_units, Ordering::Relaxed);
+        set_level(config.level);
+        SHOW_RSS.store(config.show_rss, Ordering::Relaxed);
+        set_palette(config.palette);
+    }
+
+    /// A custom duration formatter, see [set_formatter]
+    pub type Formatter = Box<dyn Fn(Duration) -> String + Send + Sync>;
+
+    pub(crate) static FORMATTER: Lazy<RwLock<Option<Formatter>>> = Lazy::new(|| RwLock::new(None));
+
+    /// Replace the default duration rendering with a custom formatter
+    ///
+    /// The formatter is used for every printed duration (total and delta in [crate::check], the result of [time_it]..)
+    pub fn set_formatter(formatter: Formatter) {
+        *FORMATTER.write().unwrap() = Some(formatter);
+    }
+    /// Restore the default duration rendering
+    pub fn reset_formatter() {
+        *FORMATTER.write().unwrap() = None;
+    }
+
+    pub(crate) struct ScopeFrame {
+        id: u64,
+        msg: Cow<'static, str>,
+        start: Instant,
+        last: Option<Duration>,
+        children: Duration,
+    }
+
+    thread_local! {
+        pub(crate) static SCOPES: RefCell<Vec<ScopeFrame>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub(crate) static SCOPE_RELATIVE: AtomicBool = AtomicBool::new(false);
+
+    /// Make [crate::check] measure relative to the innermost active [time_it] guard of the current thread
+    ///
+    /// When no guard is active the global timer is used as usual
+    pub fn set_scope_relative(enabled: bool) {
+        SCOPE_RELATIVE.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns the (total, delta) of the innermost scope and advances its delta baseline
+    pub(crate) fn scope_elapsed(advance: bool) -> Option<(Duration, Duration)> {
+        if !SCOPE_RELATIVE.load(Ordering::Relaxed) {
+            return None;
+        }
+        SCOPES.with(|scopes| {
+            let mut scopes = scopes.borrow_mut();
+            let frame = scopes.last_mut()?;
+            let total = frame.start.elapsed();
+            let delta = total - frame.last.unwrap_or_default();
+            if advance {
+                frame.last = Some(total);
+            }
+            Some((total, delta))
+        })
+    }
+
+    /// The messages of the active scopes of the current thread, outermost first
+    pub(crate) fn scope_stack() -> Vec<String> {
+        SCOPES.with(|scopes| {
+            scopes
+                .borrow()
+                .iter()
+                .map(|frame| frame.msg.to_string())
+                .collect()
+        })
+    }
+
+    /// Removes a scope from the stack, adding its duration to its parent
+    ///
+    /// Returns the total duration of its own children
+    fn end_scope(id: u64, dur: Duration) -> Duration {
+        SCOPES.with(|scopes| {
+            let mut scopes = scopes.borrow_mut();
+            let Some(idx) = scopes.iter().rposition(|frame| frame.id == id) else {
+                return Duration::ZERO;
+            };
+            let frame = scopes.remove(idx);
+            if let Some(parent) = idx.checked_sub(1).and_then(|idx| scopes.get_mut(idx)) {
+                parent.children += dur;
+            }
+            frame.children
+        })
+    }
+
+    /// Time a block of code
+    ///
+    /// The timer starts immediately when this function is called
+    ///
+    /// Its ends when the guard it returns is dropped, the message can be a literal or an owned string, example: `time_it(format!("batch {}", i))`
+    ///
+    /// Guards can be nested, see [set_scope_relative], a guard with nested guards also reports its self time (excluding the nested ones)
+    #[track_caller]
+    pub fn time_it(msg: impl Into<Cow<'static, str>>) -> impl Drop {
+        scope(msg.into())
+    }
+
+    /// Time the body of an `async fn` timed with `#[timed]`, without a scope since the future can move between threads
+    #[doc(hidden)]
+    #[track_caller]
+    pub fn time_async(msg: &'static str) -> impl Drop {
+        struct TimeAsync {
+            msg: &'static str,
+            start: Option<Instant>,
+        }
+        impl Drop for TimeAsync {
+            fn drop(&mut self) {
+                if let Some(start) = self.start {
+                    let _ = crate::print_timed(&self.msg, start.elapsed(), true);
+                }
+            }
+        }
+        let timed = crate::active() && !crate::listed(&crate::caller_site(), &msg).unwrap_or(true);
+        TimeAsync {
+            msg,
+            start: timed.then(Instant::now),
+        }
+    }
+
+    struct TimeIt {
+        id: u64,
+        msg: Cow<'static, str>,
+        start: Option<Instant>,
+    }
+    impl Drop for TimeIt {
+        fn drop(&mut self) {
+            let Some(start) = self.start else {
+                return;
+            };
+            let end = Instant::now();
+            let dur = end.duration_since(start);
+            let children = end_scope(self.id, dur);
+            crate::record::push_scope(&self.msg, dur, dur.saturating_sub(children));
+            let _ = broadcast(|| {
+                if format() == Format::Json {
+                    crate::output(
+                        JsonLine::new("scope")
+                            .str("msg", &self.msg)
+                            .num("duration_ns", dur.as_nanos())
+                            .num("self_ns", dur.saturating_sub(children).as_nanos())
+                            .finish(),
+                    )
+                } else if children.is_zero() {
+                    crate::emit(format_args!(
+                        "{}: {}",
+                        self.msg.yellow().italic(),
+                        FDur(dur, None)
+                    ))
+                } else {
+                    crate::emit(format_args!(
+                        "{}: {} (self: {})",
+                        self.msg.yellow().italic(),
+                        FDur(dur, None),
+                        FDur(dur.saturating_sub(children), None)
+                    ))
+                }
+            });
+        }
+    }
+    #[track_caller]
+    fn scope(msg: Cow<'static, str>) -> TimeIt {
+        static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        if !crate::active() || crate::listed(&crate::caller_site(), &msg).unwrap_or(true) {
+            return TimeIt {
+                id: 0,
+                start: None,
+                msg,
+            };
+        }
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        SCOPES.with(|scopes| {
+            scopes.borrow_mut().push(ScopeFrame {
+                id,
+                msg: msg.clone(),
+                start,
+                last: None,
+                children: Duration::ZERO,
+            })
+        });
+        TimeIt {
+            id,
+            start: Some(start),
+            msg,
+        }
+    }
+
+    thread_local! {
+        static RECURSION_DEPTHS: RefCell<std::collections::HashMap<Cow<'static, str>, usize>> =
+            RefCell::new(std::collections::HashMap::new());
+    }
+    // the (calls, total duration) of each depth of a recursion
+    type DepthStats = Vec<(u64, Duration)>;
+    static RECURSION_STATS: Lazy<Mutex<std::collections::HashMap<Cow<'static, str>, DepthStats>>> =
+        Lazy::new(|| Mutex::new(std::collections::HashMap::new()));
+
+    /// Time one level of a recursion, see [crate::recursion]
+    ///
+    /// The depth is counted per message and per thread, the outermost call has depth 0
+    #[track_caller]
+    pub fn recursion(msg: impl Into<Cow<'static, str>>) -> impl Drop {
+        struct Recursion {
+            msg: Cow<'static, str>,
+            // the depth of this level, None if it wasn't counted (stime was inactive)
+            depth: Option<usize>,
+            // the scope of this level, which prints the line
+            scope: TimeIt,
+        }
+        impl Drop for Recursion {
+            fn drop(&mut self) {
+                let Some(depth) = self.depth else {
+                    return;
+                };
+                // the depth is counted even if the scope isn't timed (in list mode)
+                RECURSION_DEPTHS.with(|depths| {
+                    if let Some(depth) = depths.borrow_mut().get_mut(&self.msg) {
+                        *depth = depth.saturating_sub(1);
+                    }
+                });
+                let Some(start) = self.scope.start else {
+                    return;
+                };
+                let dur = start.elapsed();
+                // print before updating the statistics, so the printed duration doesn't include it
+                drop(std::mem::replace(
+                    &mut self.scope,
+                    TimeIt {
+                        id: 0,
+                        msg: Cow::Borrowed(""),
+                        start: None,
+                    },
+                ));
+                let mut stats = RECURSION_STATS.lock().unwrap();
+                let levels = stats.entry(self.msg.clone()).or_default();
+                if levels.len() <= depth {
+                    levels.resize(depth + 1, (0, Duration::ZERO));
+                }
+                levels[depth].0 += 1;
+                levels[depth].1 += dur;
+            }
+        }
+        let msg = msg.into();
+        if !crate::active() {
+            return Recursion {
+                scope: scope(msg.clone()),
+                msg,
+                depth: None,
+            };
+        }
+        let depth = RECURSION_DEPTHS.with(|depths| {
+            let mut depths = depths.borrow_mut();
+            let depth = depths.entry(msg.clone()).or_default();
+            *depth += 1;
+            *depth - 1
+        });
+        Recursion {
+            scope: scope(format!("{} [depth {}]", msg, depth).into()),
+            msg,
+            depth: Some(depth),
+        }
+    }
+
+    /// Print the number of calls and the total time of each depth of the recursions timed with [crate::recursion]
+    ///
+    /// The time of a depth includes the deeper ones, the statistics are reset afterwards
+    pub fn print_recursion_summary() {
+        let stats = std::mem::take(&mut *RECURSION_STATS.lock().unwrap());
+        let mut stats: Vec<_> = stats.into_iter().collect();
+        stats.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let _ = broadcast(|| -> io::Result<()> {
+            for (msg, levels) in &stats {
+                for (depth, (calls, total)) in levels.iter().enumerate() {
+                    if format() == Format::Json {
+                        crate::output(
+                            JsonLine::new("recursion")
+                                .str("msg", msg)
+                                .num("depth", depth)
+                                .num("calls", calls)
+                                .num("total_ns", total.as_nanos())
+                                .finish(),
+                        )?;
+                    } else {
+                        crate::emit(format_args!(
+                            "{} [depth {}]: {} calls in {}",
+                            msg.yellow().italic(),
+                            depth,
+                            calls,
+                            FDur(*total, None)
+                        ))?;
+                    }
+                }
+            }
+            Ok(())
+        });
+    }
+
+    /// A timer passed around by value instead of using the global one, see [begin]
+    #[derive(Debug, Clone)]
+    pub struct TimerToken {
+        // `None` when stime was inactive at its creation
+        start: Option<Instant>,
+        last: Option<Duration>,
+    }
+    impl TimerToken {
+        /// Print the elapsed time since the token was created (and the delta since its previous check)
+        #[track_caller]
+        pub fn check(&mut self, msg: impl std::fmt::Display) {
+            let Some(start) = self.start else {
+                return;
+            };
+            let site = match *crate::LIST_MODE {
+                true => crate::caller_site(),
+                false => String::new(),
+            };
+            let last = &mut self.last;
+            let opts = crate::CheckOpts::default();
+            let measured = crate::measure(&msg, &site, &opts, |advance| {
+                let total = start.elapsed();
+                let delta = total - last.unwrap_or_default();
+                if advance {
+                    *last = Some(total);
+                }
+                Ok((total, delta))
+            });
+            if let Ok(Some((total, delta))) = measured {
+                let _ = crate::print_check(total, delta, &msg, &opts);
+            }
+        }
+        /// The elapsed time since the token was created, zero if stime was inactive then
+        pub fn elapsed(&self) -> Duration {
+            self.start.map(|start| start.elapsed()).unwrap_or_default()
+        }
+    }
+
+    /// Start a timer that lives in the returned token
+    ///
+    /// It can be threaded through call chains (or sent to other threads) and checked with [TimerToken::check], without touching the global timer
+    pub fn begin() -> TimerToken {
+        TimerToken {
+            start: crate::active().then(Instant::now),
+            last: None,
+        }
+    }
+
+    /// A long operation with an expected duration, see [crate::progress]
+    pub struct Progress {
+        msg: Cow<'static, str>,
+        expected: Duration,
+        start: Option<Instant>,
+        inline: bool,
+        ticked: bool,
+    }
+    impl Progress {
+        /// Redraw the progress line in place (with a carriage return) instead of printing a new line on every tick
+        pub fn inline(mut self) -> Self {
+            self.inline = true;
+            self
+        }
+        /// Print the progress: the elapsed time compared to the expected one, example: `[==========          ] 50% (5 s/10 s)`
+        ///
+        /// The percentage stops at 100%, an overrun shows in the times (`(12 s/10 s)`) and in the red final line
+        pub fn tick(&mut self) {
+            let Some(start) = self.start else {
+                return;
+            };
+            let elapsed = start.elapsed();
+            if format() == Format::Json {
+                let _ = crate::output(
+                    JsonLine::new("progress")
+                        .str("msg", &self.msg)
+                        .num("elapsed_ns", elapsed.as_nanos())
+                        .num("expected_ns", self.expected.as_nanos())
+                        .finish(),
+                );
+                return;
+            }
+            const WIDTH: usize = 20;
+            // an operation expected to take no time is done as soon as it starts
+            let ratio = match self.expected.as_secs_f64() {
+                0.0 => 1.0,
+                expected => (elapsed.as_secs_f64() / expected).min(1.0),
+            };
+            let filled = ((ratio * WIDTH as f64) as usize).min(WIDTH);
+            let line = crate::render(format_args!(
+                "{} [{}{}] {:.0}% ({}/{})",
+                self.msg.yellow().italic(),
+                "=".repeat(filled),
+                " ".repeat(WIDTH - filled),
+                ratio * 100.0,
+                FDur(elapsed, None),
+                FDur(self.expected, None)
+            ));
+            if self.inline {
+                let mut target = OUTPUT_TARGET.get();
+                let _ = write!(target, "\r{}", line);
+                let _ = target.flush();
+            } else {
+                let _ = crate::output(line);
+            }
+            self.ticked = true;
+        }
+    }
+    impl Drop for Progress {
+        fn drop(&mut self) {
+            let Some(start) = self.start else {
+                return;
+            };
+            let total = start.elapsed();
+            if self.inline && self.ticked && format() != Format::Json {
+                // end the line of the progress bar
+                let _ = writeln!(OUTPUT_TARGET.get());
+            }
+            let _ = crate::print_timed(&self.msg, total, total <= self.expected);
+        }
+    }
+
+    /// Start a [Progress], see [crate::progress]
+    #[track_caller]
+    pub fn progress(msg: impl Into<Cow<'static, str>>, expected: Duration) -> Progress {
+        let msg = msg.into();
+        let timed = crate::active() && !crate::listed(&crate::caller_site(), &msg).unwrap_or(true);
+        Progress {
+            msg,
+            expected,
+            start: timed.then(Instant::now),
+            inline: false,
+            ticked: false,
+        }
+    }
+
+    pub(crate) struct RunStats {
+        checks: usize,
+        slowest: Option<(String, Duration)>,
+    }
+
+    pub(crate) static RUN_STATS: Mutex<Option<RunStats>> = Mutex::new(None);
+
+    pub(crate) fn count_run_check(msg: &dyn std::fmt::Display, delta: Duration) {
+        if let Some(stats) = &mut *RUN_STATS.lock().unwrap() {
+            stats.checks += 1;
+            if stats
+                .slowest
+                .as_ref()
+                .is_none_or(|(_, slowest)| delta > *slowest)
+            {
+                stats.slowest = Some((msg.to_string(), delta));
+            }
+        }
+    }
+
+    /// A run started with [run], it prints its summary when dropped
+    pub struct RunGuard {
+        name: &'static str,
+        start: Option<Instant>,
+    }
+    impl Drop for RunGuard {
+        fn drop(&mut self) {
+            let Some(start) = self.start else {
+                return;
+            };
+            let total = start.elapsed();
+            let Some(stats) = RUN_STATS.lock().unwrap().take() else {
+                return;
+            };
+            let _ = broadcast(|| {
+                if format() == Format::Json {
+                    let mut line = JsonLine::new("run")
+                        .str("msg", &self.name)
+                        .num("total_ns", total.as_nanos())
+                        .num("checks", stats.checks);
+                    if let Some((msg, delta)) = &stats.slowest {
+                        line = line.str("slowest", msg).num("slowest_ns", delta.as_nanos());
+                    }
+                    crate::output(line.finish())
+                } else {
+                    match &stats.slowest {
+                        Some((msg, delta)) => crate::emit(format_args!(
+                            "{} {} in {}: {} checks, slowest: {} ({})",
+                            "Finished".red().bold(),
+                            self.name.light_blue().italic(),
+                            FDur(total, None),
+                            stats.checks,
+                            msg.light_blue().italic(),
+                            FDur(*delta, None)
+                        )),
+                        None => crate::emit(format_args!(
+                            "{} {} in {}",
+                            "Finished".red().bold(),
+                            self.name.light_blue().italic(),
+                            FDur(total, None)
+                        )),
+                    }
+                }
+            });
+            drain_async_output(Duration::MAX);
+            let _ = OUTPUT_TARGET.get().flush();
+        }
+    }
+
+    /// Start a named run: restart the timer, and when the returned guard is dropped print a summary
+    /// (total time, number of checks and the slowest one) then flush [OUTPUT_TARGET]
+    ///
+    /// With [set_async_output] the guard waits for the pending lines (the summary included) to be written
+    ///
+    /// Only one run is tracked at a time, starting a new one ends the tracking of the previous one
+    ///
+    /// It does nothing when stime is inactive
+    pub fn run(name: &'static str) -> RunGuard {
+        if !crate::active() || *crate::LIST_MODE {
+            return RunGuard { name, start: None };
+        }
+        *RUN_STATS.lock().unwrap() = Some(RunStats {
+            checks: 0,
+            slowest: None,
+        });
+        let _ = crate::restart();
+        let _ = crate::starting(concat!(file!(), ":", line!()), &name);
+        RunGuard {
+            name,
+            start: Some(Instant::now()),
+        }
+    }
+
+    static WATCHDOGS: AtomicU32 = AtomicU32::new(0);
+    // when the last check was made, and its message
+    static LAST_CHECK: Mutex<Option<(Instant, String)>> = Mutex::new(None);
+
+    /// Note a check for the watchdogs, see [watchdog]
+    pub(crate) fn ping_watchdogs(msg: &dyn std::fmt::Display) {
+        if WATCHDOGS.load(Ordering::Relaxed) != 0 {
+            *LAST_CHECK.lock().unwrap() = Some((Instant::now(), msg.to_string()));
+        }
+    }
+
+    /// A watchdog started with [watchdog], it is stopped when dropped
+    pub struct Watchdog {
+        stop: Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
+    }
+    impl Drop for Watchdog {
+        fn drop(&mut self) {
+            if let Some((stop, handle)) = self.stop.take() {
+                drop(stop);
+                let _ = handle.join();
+                WATCHDOGS.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Call `on_timeout` from a background thread when no [crate::check] was made for `timeout`, to catch silent hangs
+    ///
+    /// It receives the message of the last check (`None` if there was none since the watchdog started) and how long ago it was made,
+    /// example: `watchdog(Duration::from_secs(5), |last, stalled| eprintln!("stuck after {:?} for {:?}", last, stalled))`.
+    /// It is called once per hang, the next check re-arms it
+    ///
+    /// It does nothing when stime is inactive
+    pub fn watchdog(
+        timeout: Duration,
+        on_timeout: impl Fn(Option<&str>, Duration) + Send + 'static,
+    ) -> Watchdog {
+        if !crate::active() {
+            return Watchdog { stop: None };
+        }
+        WATCHDOGS.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let mut fired: Option<Instant> = None;
+            loop {
+                let (last, msg) = match &*LAST_CHECK.lock().unwrap() {
+                    Some((at, msg)) if *at > started => (*at, Some(msg.clone())),
+                    _ => (started, None),
+                };
+                let stalled = last.elapsed();
+                let wait = if stalled < timeout {
+                    timeout - stalled
+                } else {
+                    if fired != Some(last) {
+                        fired = Some(last);
+                        on_timeout(msg.as_deref(), stalled);
+                    }
+                    timeout
+                };
+                // the sender is dropped with the watchdog
+                if stopped.recv_timeout(wait) != Err(mpsc::RecvTimeoutError::Timeout) {
+                    return;
+                }
+            }
+        });
+        Watchdog {
+            stop: Some((stop, handle)),
+        }
+    }
+
+    pub(crate) static MILESTONES: Mutex<Vec<Duration>> = Mutex::new(Vec::new());
+    static MILESTONES_THREAD: Mutex<Option<thread::Thread>> = Mutex::new(None);
+
+    /// Print a line from a background thread each time the total time since the last [crate::start] crosses one of `milestones`,
+    /// to show that a long operation is still running without adding checks
+    ///
+    /// Example: `set_milestones(&[Duration::from_secs(1), Duration::from_secs(5), Duration::from_secs(30)])`
+    /// prints `Still running: 5 s elapsed` after 5 s. Each milestone is printed once per [crate::start],
+    /// an empty slice stops the thread (which is only started while stime is active)
+    pub fn set_milestones(milestones: &[Duration]) {
+        let mut watcher = MILESTONES_THREAD.lock().unwrap();
+        let mut sorted = milestones.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        *MILESTONES.lock().unwrap() = sorted;
+        match &*watcher {
+            Some(thread) => thread.unpark(),
+            None if crate::active() && !milestones.is_empty() => {
+                *watcher = Some(thread::spawn(watch_milestones).thread().clone());
+            }
+            None => {}
+        }
+    }
+
+    /// Make the milestones thread notice a restart of the timer
+    pub(crate) fn wake_milestones() {
+        if let Some(thread) = &*MILESTONES_THREAD.lock().unwrap() {
+            thread.unpark();
+        }
+    }
+
+    fn watch_milestones() {
+        let mut start = *state().chrono.lock().unwrap();
+        let mut last_fired = None;
+        loop {
+            let chrono = *state().chrono.lock().unwrap();
+            if chrono != start {
+                start = chrono;
+                last_fired = None;
+            }
+            let milestones = {
+                let mut watcher = MILESTONES_THREAD.lock().unwrap();
+                let milestones = MILESTONES.lock().unwrap().clone();
+                if milestones.is_empty() {
+                    *watcher = None;
+                    return;
+                }
+                milestones
+            };
+            let elapsed = start.elapsed();
+            let crossed = milestones
+                .iter()
+                .rev()
+                .find(|milestone| **milestone <= elapsed);
+            if crossed.is_some() && crossed > last_fired.as_ref() {
+                last_fired = crossed.copied();
+                if crate::active() {
+                    print_milestone(elapsed);
+                }
+            }
+            match milestones.iter().find(|milestone| **milestone > elapsed) {
+                Some(next) => thread::park_timeout(*next - elapsed),
+                None => thread::park(),
+            }
+        }
+    }
+
+    fn print_milestone(elapsed: Duration) {
+        let _ = broadcast(|| {
+            if format() == Format::Json {
+                crate::output(
+                    JsonLine::new("milestone")
+                        .num("elapsed_ns", elapsed.as_nanos())
+                        .finish(),
+                )
+            } else {
+                crate::emit(format_args!(
+                    "{} {} elapsed",
+                    "Still running:".bold(),
+                    FDur(elapsed, None)
+                ))
+            }
+        });
+    }
+
+    pub(crate) static OVERHEAD: Mutex<Duration> = Mutex::new(Duration::ZERO);
+
+    /// Measure the overhead stime adds to each [crate::check] (reading the timer, rendering the line..),
+    /// it is then subtracted from the deltas of the checks. Returns the measured overhead
+    ///
+    /// It is approximate: the cost of writing the line is not included and varies between runs,
+    /// it is meant for fine-grained measurements where the overhead is significant. The timer state is left as it was,
+    /// except that the delta of the next check starts after the calibration instead of including it
+    pub fn calibrate() -> Duration {
+        const SAMPLES: usize = 1000;
+        if !crate::active() {
+            return Duration::ZERO;
+        }
+        let snapshot = snapshot();
+        let mut deltas = Vec::with_capacity(SAMPLES);
+        // the first sample has the delta since the last check
+        let _ = crate::calibration_sample();
+        for _ in 0..SAMPLES {
+            if let Ok(delta) = crate::calibration_sample() {
+                deltas.push(delta);
+            }
+        }
+        restore(snapshot);
+        let state = state();
+        let now = state.chrono.lock().unwrap().elapsed();
+        *state.last_duration.lock().unwrap() = Some(now);
+        deltas.sort();
+        let overhead = deltas.get(deltas.len() / 2).copied().unwrap_or_default();
+        *OVERHEAD.lock().unwrap() = overhead;
+        overhead
+    }
+    /// Stop subtracting the overhead measured by [calibrate]
+    pub fn clear_calibration() {
+        *OVERHEAD.lock().unwrap() = Duration::ZERO;
+    }
+
+    pub(crate) static LOCK_THRESHOLD: Mutex<Duration> = Mutex::new(Duration::from_millis(1));
+
+    /// Only print the waits of [timed_lock] longer than `threshold`, it is 1 ms by default
+    pub fn set_lock_threshold(threshold: Duration) {
+        *LOCK_THRESHOLD.lock().unwrap() = threshold;
+    }
+
+    /// Lock `mutex` and print how long acquiring it took, if it was longer than the threshold (see [set_lock_threshold])
+    ///
+    /// Example: `let queue = timed_lock(&QUEUE, "queue").unwrap();` prints `lock queue: waited 3 ms` under contention
+    pub fn timed_lock<'a, T>(
+        mutex: &'a Mutex<T>,
+        name: impl std::fmt::Display,
+    ) -> std::sync::LockResult<MutexGuard<'a, T>> {
+        if !crate::active() || *crate::LIST_MODE {
+            return mutex.lock();
+        }
+        let start = Instant::now();
+        let guard = mutex.lock();
+        let wait = start.elapsed();
+        if wait > *LOCK_THRESHOLD.lock().unwrap() {
+            let _ = broadcast(|| {
+                if format() == Format::Json {
+                    crate::output(
+                        JsonLine::new("lock")
+                            .str("msg", &name)
+                            .num("wait_ns", wait.as_nanos())
+                            .finish(),
+                    )
+                } else {
+                    crate::emit(format_args!(
+                        "{} {}: waited {}",
+                        "lock".bold(),
+                        name.yellow().italic(),
+                        FDur(wait, None)
+                    ))
+                }
+            });
+        }
+        guard
+    }
+
+    /// Install a panic hook that prints the elapsed time since the last [crate::start] and flushes the output target
+    ///
+    /// The previously installed hook is still called afterwards
+    pub fn install_panic_reporter() {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if crate::active() {
+                report_panic();
+            }
+            previous(info);
+        }));
+    }
+
+    fn report_panic() {
+        // the lines queued before the panic go first, the writer thread may be the one panicking so it is not waited for long
+        drain_async_output(Duration::from_secs(1));
+        // the panic might have happened while stime was holding one of these locks
+        let state = state();
+        let Ok(total) = state.chrono.try_lock().map(|chrono| chrono.elapsed()) else {
+            return;
+        };
+        let line = crate::render(format_args!(
+            "{} {}",
+            "Panicked after".red().bold(),
+            FDur(total, None)
+        ));
+        if let Ok(mut target) = state.target.inner.try_lock() {
+            let _ = writeln!(target, "{}", line);
+            let _ = target.flush();
+        }
+    }
+
+    /// Convenient custom log wrapper
+    ///
+    /// It wraps an Arc so it can be cloned freely
+    #[derive(Default)]
+    pub struct CustomLog<W> {
+        log: Arc<Mutex<W>>,
+    }
+    impl<W> Clone for CustomLog<W> {
+        fn clone(&self) -> Self {
+            Self {
+                log: self.log.clone(),
+            }
+        }
+    }
+    impl<W> CustomLog<W> {
+        fn lock(&self) -> MutexGuard<'_, W> {
+            self.log.lock().unwrap()
+        }
+    }
+    impl<W: io::Read> CustomLog<W> {
+        /// Create a CustomLog from a custom type
+        pub fn new(log: W) -> Self {
+            Self {
+                log: Arc::new(Mutex::new(log)),
+            }
+        }
+        /// Read The log
+        pub fn read(&self) -> io::Result<String> {
+            let mut s = String::new();
+            self.lock().read_to_string(&mut s)?;
+            Ok(s)
+        }
+    }
+    impl<W: io::Write> io::Write for CustomLog<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.lock().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.lock().flush()
+        }
+    }
+
+    /// An in-memory log, see [capture]
+    #[derive(Default, Clone)]
+    pub struct MemoryLog(CustomLog<Vec<u8>>);
+    impl MemoryLog {
+        /// Everything written to the log so far, decoded lossily so use [MemoryLog::bytes] for [Format::Binary]
+        pub fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock()).into_owned()
+        }
+        /// The raw bytes written to the log so far, example: `read_binary_log(&log.bytes()[..])`
+        pub fn bytes(&self) -> Vec<u8> {
+            self.0.lock().clone()
+        }
+        /// Empty the log
+        pub fn clear(&self) {
+            self.0.lock().clear();
+        }
+    }
+    impl io::Write for MemoryLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    /// An output target sending each line to a channel, see [channel_target]
+    pub struct ChannelWriter {
+        sender: mpsc::Sender<String>,
+        buf: Vec<u8>,
+    }
+    impl io::Write for ChannelWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buf.extend_from_slice(buf);
+            while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line[..end]).into_owned();
+                // a dropped receiver just means nobody is listening anymore
+                let _ = self.sender.send(line);
+            }
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Install an output target that sends each line (without the trailing newline) to the returned channel
+    ///
+    /// Handy for event loops (GUI, async..) that poll the receiver and display the lines themselves
+    pub fn channel_target() -> mpsc::Receiver<String> {
+        let (sender, receiver) = mpsc::channel();
+        OUTPUT_TARGET.set(ChannelWriter {
+            sender,
+            buf: Vec::new(),
+        });
+        receiver
+    }
+
+    /// Capture all the output in memory, by installing a [MemoryLog] as the output target
+    ///
+    /// Handy in tests: capture (after the [crate::start], which puts stderr back), run the code, then assert on `log.contents()`
+    pub fn capture() -> MemoryLog {
+        let log = MemoryLog::default();
+        OUTPUT_TARGET.set(log.clone());
+        log
+    }
+}
+
+#[doc(hidden)]
+pub fn restart() -> Result<(), Box<dyn std::error::Error>> {
+    let state = advanced::state();
+    *state.chrono.lock()? = Instant::now();
+    *state.last_duration.lock()? = None;
+    state
+        .started
+        .store(true, std::sync::atomic::Ordering::Relaxed);
+    advanced::reset_cpu();
+    advanced::reset_rss();
+    advanced::wake_milestones();
+    advanced::OUTPUT_TARGET.restore_scoped();
+    Ok(())
+}
+
+#[doc(hidden)]
+pub struct CheckOpts {
+    pub site: Option<&'static str>,
+    pub unit: Option<advanced::Unit>,
+    pub tag: Option<&'static str>,
+    pub at: Option<Instant>,
+    pub fields: Vec<(&'static str, String)>,
+    pub level: u32,
+    pub rate: Option<(&'static str, f64)>,
+    pub peek: bool,
+    pub zero: bool,
+    pub slower_than: Option<Duration>,
+}
+impl Default for CheckOpts {
+    fn default() -> Self {
+        Self {
+            site: None,
+            unit: None,
+            tag: None,
+            at: None,
+            fields: Vec::new(),
+            level: 1,
+            rate: None,
+            peek: false,
+            zero: false,
+            slower_than: None,
+        }
+    }
+}
+impl CheckOpts {
+    pub fn new(site: &'static str) -> Self {
+        Self {
+            site: Some(site),
+            ..Default::default()
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn default_label(site: &'static str, function: &'static str) -> Cow<'static, str> {
+    if !advanced::LABEL_FUNCTION.load(std::sync::atomic::Ordering::Relaxed) {
+        return Cow::Borrowed(site);
+    }
+    // the name is the path of the `f` item defined at the call site, which can be in closures
+    let mut function = function.strip_suffix("::f").unwrap_or(function);
+    while let Some(outer) = function.strip_suffix("::{{closure}}") {
+        function = outer;
+    }
+    Cow::Owned(format!("{} ({})", site, function))
+}
+
+#[doc(hidden)]
+pub fn checkpoint(
+    msg: &dyn std::fmt::Display,
+    opts: CheckOpts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match measure(msg, opts.site.unwrap_or_default(), &opts, elapsed_with)? {
+        Some((total_time, delta)) => print_check(total_time, delta, msg, &opts),
+        None => Ok(()),
+    }
+}
+
+/// Take the (total, delta) of a check from `elapsed` (which advances the delta baseline if given true), `None` if it is not printed
+///
+/// It is the part shared by every kind of check: list mode, watchdogs, level and tag filters, options and calibration
+fn measure(
+    msg: &dyn std::fmt::Display,
+    site: &str,
+    opts: &CheckOpts,
+    elapsed: impl FnOnce(bool) -> Result<(Duration, Duration), Box<dyn std::error::Error>>,
+) -> Result<Option<(Duration, Duration)>, Box<dyn std::error::Error>> {
+    advanced::ping_watchdogs(msg);
+    if listed(site, msg)? {
+        return Ok(None);
+    }
+    if opts.level > advanced::level() || !advanced::tag_allowed(opts.tag) {
+        return Ok(None);
+    }
+    let (total_time, delta) = elapsed(!opts.peek)?;
+    let total_time = opts.at.map_or(total_time, |at| at.elapsed());
+    let delta = delta.saturating_sub(*advanced::OVERHEAD.lock()?);
+    let delta = if opts.zero { Duration::ZERO } else { delta };
+    if opts.slower_than.is_some_and(|threshold| delta <= threshold) {
+        return Ok(None);
+    }
+    Ok(Some((total_time, delta)))
+}
+
+/// A message built by a closure each time it is displayed, for [check_if_slow]
+#[doc(hidden)]
+pub struct LazyMsg<F>(pub F);
+impl<F: Fn() -> T, T: std::fmt::Display> std::fmt::Display for LazyMsg<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        (self.0)().fmt(f)
+    }
+}
+
+/// `STIME=list` only lists the instrumented call sites instead of timing them
+static LIST_MODE: Lazy<bool> = Lazy::new(|| std::env::var("STIME").is_ok_and(|v| v == "list"));
+
+/// Print a call site (and its message) the first time it is reached
+fn list_site(site: &str, msg: &dyn std::fmt::Display) -> std::io::Result<()> {
+    static SEEN: Lazy<Mutex<std::collections::HashSet<String>>> = Lazy::new(Default::default);
+    if !SEEN.lock().unwrap().insert(site.to_owned()) {
+        return Ok(());
+    }
+    if advanced::format() == advanced::Format::Json {
+        return output(
+            format::JsonLine::new("site")
+                .str("site", &site)
+                .str("msg", msg)
+                .finish(),
+        );
+    }
+    let msg = msg.to_string();
+    // default labels start with the site
+    if let Some(function) = msg.strip_prefix(site) {
+        emit(format_args!("{}{}", site.light_blue(), function))
+    } else {
+        emit(format_args!("{} {}", site.light_blue(), msg.italic()))
+    }
+}
+
+#[doc(hidden)]
+pub fn list_mode() -> bool {
+    *LIST_MODE
+}
+
+/// In list mode, list the call site and return true so the caller skips the timing
+#[doc(hidden)]
+pub fn listed(site: &str, msg: &dyn std::fmt::Display) -> std::io::Result<bool> {
+    if !*LIST_MODE {
+        return Ok(false);
+    }
+    list_site(site, msg)?;
+    Ok(true)
+}
+
+/// The call site of a `#[track_caller]` function, for list mode
+#[track_caller]
+fn caller_site() -> String {
+    let caller = std::panic::Location::caller();
+    format!("{}:{}", caller.file(), caller.line())
+}
+
+/// Returns the (total, delta) of the current timer (the innermost scope or the global one) and advances its delta baseline
+#[doc(hidden)]
+pub fn elapsed() -> Result<(Duration, Duration), Box<dyn std::error::Error>> {
+    elapsed_with(true)
+}
+
+/// Like [elapsed], the delta baseline is only advanced if `advance` is true
+fn elapsed_with(advance: bool) -> Result<(Duration, Duration), Box<dyn std::error::Error>> {
+    match advanced::scope_elapsed(advance) {
+        Some(elapsed) => Ok(elapsed),
+        None => global_elapsed(advance),
+    }
+}
+
+/// Keep a measured check in the records and the run stats
+fn count_check(
+    total_time: Duration,
+    delta: Duration,
+    msg: &dyn std::fmt::Display,
+    opts: &CheckOpts,
+) {
+    record::push(msg, total_time, delta, opts);
+    advanced::count_run_check(msg, delta);
+}
+
+fn print_check(
+    total_time: Duration,
+    delta: Duration,
+    msg: &dyn std::fmt::Display,
+    opts: &CheckOpts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    count_check(total_time, delta, msg, opts);
+    let suppressed = match advanced::rate_limit() {
+        Some(suppressed) => suppressed,
+        None => return Ok(()),
+    };
+    let measures = Measures::take(delta, opts);
+    advanced::broadcast(|| {
+        if suppressed != 0 {
+            if advanced::format() == advanced::Format::Json {
+                output(
+                    format::JsonLine::new("suppressed")
+                        .num("count", suppressed)
+                        .finish(),
+                )?;
+            } else {
+                emit(format_args!(
+                    "{}",
+                    format_args!("({} lines suppressed)", suppressed).yellow()
+                ))?;
+            }
+        }
+        if advanced::format() == advanced::Format::Binary {
+            return output_binary(&format::binary_record(total_time, delta, msg));
+        }
+        output(render_check(total_time, delta, msg, opts, &measures))
+    })
+    .map_err(Into::into)
+}
+
+/// Take a check and return its line instead of printing it
+#[doc(hidden)]
+pub fn check_string(
+    msg: &dyn std::fmt::Display,
+    opts: &CheckOpts,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let Some((total_time, delta)) =
+        measure(msg, opts.site.unwrap_or_default(), opts, elapsed_with)?
+    else {
+        return Ok(String::new());
+    };
+    count_check(total_time, delta, msg, opts);
+    let measures = Measures::take(delta, opts);
+    Ok(render_check(total_time, delta, msg, opts, &measures))
+}
+
+/// The measurements of a check that update some state, so they are only taken once even if the check is printed twice
+struct Measures {
+    ema: Option<Duration>,
+    trend: Option<std::cmp::Ordering>,
+    cpu: Option<Duration>,
+    rss: Option<i64>,
+    mono: Option<Duration>,
+}
+impl Measures {
+    fn take(delta: Duration, opts: &CheckOpts) -> Self {
+        Self {
+            ema: opts.site.and_then(|site| advanced::ema(site, delta)),
+            trend: opts.site.and_then(|site| advanced::trend(site, delta)),
+            cpu: advanced::cpu_delta(),
+            rss: advanced::rss_delta(),
+            mono: advanced::mono(),
+        }
+    }
+}
+
+/// Take a check without printing it (its line is still rendered), and return its delta, see [advanced::calibrate]
+fn calibration_sample() -> Result<Duration, Box<dyn std::error::Error>> {
+    let (total_time, delta) = elapsed_with(true)?;
+    let measures = Measures {
+        ema: None,
+        trend: None,
+        cpu: None,
+        rss: None,
+        mono: None,
+    };
+    let line = render_check(
+        total_time,
+        delta,
+        &"calibration",
+        &CheckOpts::default(),
+        &measures,
+    );
+    std::hint::black_box(line);
+    Ok(delta)
+}
+
+fn render_check(
+    total_time: Duration,
+    delta: Duration,
+    msg: &dyn std::fmt::Display,
+    opts: &CheckOpts,
+    measures: &Measures,
+) -> String {
+    use std::fmt::Write;
+    let ema = measures.ema;
+    if advanced::format() == advanced::Format::Json {
+        let mut line = format::JsonLine::new("check")
+            .str("msg", msg)
+            .num("total_ns", total_time.as_nanos())
+            .num("delta_ns", delta.as_nanos());
+        if let Some(ema) = ema {
+            line = line.num("ema_ns", ema.as_nanos());
+        }
+        if let Some(tag) = opts.tag {
+            line = line.str("tag", &tag);
+        }
+        for (key, value) in &opts.fields {
+            // values that print like numbers are kept as JSON numbers
+            let is_number = value.parse::<i128>().is_ok_and(|n| n.to_string() == *value)
+                || value
+                    .parse::<f64>()
+                    .is_ok_and(|n| n.is_finite() && n.to_string() == *value);
+            line = if is_number {
+                line.num(key, value)
+            } else {
+                line.str(key, value)
+            };
+        }
+        if let Some((items, count)) = opts.rate {
+            line = line.str("items", &items).num("count", count);
+            if !delta.is_zero() {
+                line = line.num("per_sec", count / delta.as_secs_f64());
+            }
+        }
+        if let Some(cpu) = measures.cpu {
+            line = line.num("cpu_ns", cpu.as_nanos());
+        }
+        if let Some(rss) = measures.rss {
+            line = line.num("rss_delta", rss);
+        }
+        if let Some(mono) = measures.mono {
+            line = line.num("mono_ns", mono.as_nanos());
+        }
+        return line.finish();
+    }
+    let unit = check_unit(opts.unit, total_time, delta);
+    let delta_text = match measures.trend {
+        Some(std::cmp::Ordering::Less) => {
+            let text = format!(
+                "{}",
+                format_args!("{} ↓", strip_ansi(&FDur(delta, unit).to_string())).green()
+            );
+            format::value_color(&text)
+        }
+        Some(std::cmp::Ordering::Greater) => {
+            let text = format!(
+                "{}",
+                format_args!("{} ↑", strip_ansi(&FDur(delta, unit).to_string())).red()
+            );
+            format::value_color(&text)
+        }
+        _ => FDur(delta, unit).to_string(),
+    };
+    let mut extras = String::new();
+    if let Some(cpu) = measures.cpu {
+        let _ = write!(
+            extras,
+            " {}{} {}{}",
+            "[".light_blue(),
+            "CPU:".bold(),
+            FDur(cpu, unit),
+            "]".light_blue()
+        );
+    }
+    if let Some(rss) = measures.rss {
+        let _ = write!(
+            extras,
+            " {}{} {:+} B{}",
+            "[".light_blue(),
+            "RSS:".bold(),
+            rss,
+            "]".light_blue()
+        );
+    }
+    let mut fields = String::new();
+    for (key, value) in &opts.fields {
+        let _ = write!(fields, " {}={}", key.bold(), value);
+    }
+    if let Some((items, count)) = opts.rate {
+        let _ = write!(
+            fields,
+            " ({} {} / {} = {})",
+            count,
+            items,
+            FDur(delta, unit),
+            format_args!("{} {}/s", format::si(count / delta.as_secs_f64()), items).bold()
+        );
+    }
+    let mut times = times(total_time, delta_text, ema, unit);
+    if let Some(mono) = measures.mono {
+        times = format!(
+            "{} {}",
+            format_args!("@{}ns", mono.as_nanos()).bold(),
+            times
+        );
+    }
+    render(format_args!(
+        //times [extras] #tag msg fields
+        "{}{} {}{}{}",
+        times,
+        extras,
+        opts.tag
+            .map(|tag| format!("{} ", format_args!("#{}", tag).bold()))
+            .unwrap_or_default(),
+        msg.light_blue().italic(),
+        fields
+    ))
+}
+
+/// The unit of the durations of a check, `None` for the default one
+fn check_unit(
+    unit: Option<advanced::Unit>,
+    total_time: Duration,
+    delta: Duration,
+) -> Option<advanced::Unit> {
+    unit.or_else(|| {
+        let consistent = advanced::CONSISTENT_UNITS.load(std::sync::atomic::Ordering::Relaxed)
+            && *advanced::UNIT.lock().unwrap() == advanced::Unit::Auto;
+        consistent.then(|| FDur(total_time.max(delta), None).auto_unit())
+    })
+}
+
+/// The durations segment of a check line, `delta_text` is the rendered delta
+fn times(
+    total_time: Duration,
+    delta_text: String,
+    ema: Option<Duration>,
+    unit: Option<advanced::Unit>,
+) -> String {
+    if advanced::SPLIT_MODE.load(std::sync::atomic::Ordering::Relaxed) {
+        //[+ti]
+        format!(
+            "{}{}{}",
+            "[+".light_blue(),
+            FDur(total_time, unit),
+            "]".light_blue()
+        )
+    } else {
+        //[T  ti  /  D  ti (avg)]
+        format!(
+            "{}{} {} {} {} {}{}{}",
+            "[".light_blue(),
+            "TotalTime:".bold(),
+            FDur(total_time, unit),
+            "/".light_blue(),
+            "DeltaTime:".bold(),
+            delta_text,
+            ema.map(|ema| format!(" (avg {})", FDur(ema, unit)))
+                .unwrap_or_default(),
+            "]".light_blue()
+        )
+    }
+}
+
+#[doc(hidden)]
+pub fn eprint_timed(args: std::fmt::Arguments) {
+    if !active() || *LIST_MODE {
+        eprintln!("{}", args);
+        return;
+    }
+    let elapsed = advanced::state().chrono.lock().unwrap().elapsed();
+    let prefix = recolor(format!(
+        "{}{}{}",
+        "[+".light_blue(),
+        FDur(elapsed, None),
+        "]".light_blue()
+    ));
+    eprintln!("{} {}", prefix, args);
+}
+
+#[doc(hidden)]
+pub fn starting(
+    site: &'static str,
+    msg: &dyn std::fmt::Display,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if *LIST_MODE {
+        return list_site(site, msg).map_err(Into::into);
+    }
+    static STARTED_BEFORE: AtomicBool = AtomicBool::new(false);
+    let first = !STARTED_BEFORE.swap(true, std::sync::atomic::Ordering::Relaxed);
+    record::started(msg);
+    advanced::broadcast(|| {
+        if advanced::format() == advanced::Format::Json {
+            let mut line = format::JsonLine::new("start").str("msg", msg);
+            if let Some(name) = &*advanced::TIMER_NAME.lock()? {
+                line = line.str("timer", name);
+            }
+            return output(line.finish()).map_err(Into::into);
+        }
+        if let Some(separator) = &*advanced::SESSION_SEPARATOR.lock()? {
+            if !first {
+                emit(format_args!("{}", separator.light_blue()))?;
+            }
+        }
+        match &*advanced::TIMER_NAME.lock()? {
+            Some(name) => emit(format_args!(
+                "{} {} {}",
+                "Starting".red().bold(),
+                format_args!("[{}]", name).bold(),
+                msg.light_blue().italic()
+            ))?,
+            None => emit(format_args!(
+                "{} {}",
+                "Starting".red().bold(),
+                msg.light_blue().italic()
+            ))?,
+        }
+        Ok(())
+    })
+}
+
+#[doc(hidden)]
+pub fn print_timed(
+    msg: &dyn std::fmt::Display,
+    elapsed: Duration,
+    ok: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    advanced::broadcast(|| {
+        if advanced::format() == advanced::Format::Json {
+            let line = format::JsonLine::new("timed")
+                .str("msg", msg)
+                .num("duration_ns", elapsed.as_nanos())
+                .bool("ok", ok);
+            return output(line.finish()).map_err(Into::into);
+        }
+        if ok {
+            emit(format_args!(
+                "{}: {}",
+                msg.yellow().italic(),
+                FDur(elapsed, None)
+            ))?;
+        } else {
+            emit(format_args!(
+                "{}: {}",
+                msg.red().italic(),
+                FDur(elapsed, None)
+            ))?;
+        }
+        Ok(())
+    })
+}
+
+#[doc(hidden)]
+pub fn print_bench(
+    msg: &dyn std::fmt::Display,
+    iterations: u64,
+    total: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ops_per_sec = iterations as f64 / total.as_secs_f64();
+    let per_iteration_ns = total.as_nanos() as f64 / iterations as f64;
+    advanced::broadcast(|| {
+        if advanced::format() == advanced::Format::Json {
+            let mut line = format::JsonLine::new("bench")
+                .str("msg", msg)
+                .num("iterations", iterations)
+                .num("total_ns", total.as_nanos());
+            // without iterations there is no per iteration figure
+            if iterations > 0 {
+                line = line.num("per_iter_ns", per_iteration_ns);
+                if ops_per_sec.is_finite() {
+                    line = line.num("ops_per_sec", ops_per_sec);
+                }
+            }
+            return output(line.finish());
+        }
+        if iterations == 0 {
+            return emit(format_args!(
+                "{}: 0 iterations in {}",
+                msg.yellow().italic(),
+                FDur(total, None)
+            ));
+        }
+        emit(format_args!(
+            "{}: {} iterations in {}, {}/iter ({})",
+            msg.yellow().italic(),
+            iterations,
+            FDur(total, None),
+            format::FMean(total, iterations),
+            format_args!("{} ops/s", format::si(ops_per_sec)).bold()
+        ))
+    })
+    .map_err(Into::into)
+}
+
+static SPANS: Lazy<Mutex<HashMap<&'static str, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[doc(hidden)]
+pub fn span_start(name: &'static str) {
+    SPANS.lock().unwrap().insert(name, Instant::now());
+}
+
+#[doc(hidden)]
+pub fn span_end(name: &'static str) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(start) = SPANS.lock()?.remove(name) else {
+        return warn(&format!(
+            "span_end! called for {:?} without span_start!",
+            name
+        ))
+        .map_err(Into::into);
+    };
+    let duration = start.elapsed();
+    record::push(&name, duration, duration, &CheckOpts::default());
+    advanced::broadcast(|| {
+        if advanced::format() == advanced::Format::Json {
+            let line = format::JsonLine::new("span")
+                .str("msg", &name)
+                .num("duration_ns", duration.as_nanos());
+            return output(line.finish());
+        }
+        emit(format_args!(
+            "{}: {}",
+            name.yellow().italic(),
+            FDur(duration, None)
+        ))
+    })
+    .map_err(Into::into)
+}
+
+fn warn(msg: &str) -> std::io::Result<()> {
+    advanced::broadcast(|| {
+        if advanced::format() == advanced::Format::Json {
+            return output(format::JsonLine::new("warning").str("msg", &msg).finish());
+        }
+        emit(format_args!("{}", msg.yellow()))
+    })
+}
+
+/// Returns the (total, delta) of the global timer and advances its delta baseline if `advance` is true
+fn global_elapsed(advance: bool) -> Result<(Duration, Duration), Box<dyn std::error::Error>> {
+    use std::sync::atomic::Ordering;
+    let state = advanced::state();
+    let started = state.started.load(Ordering::Relaxed);
+    if !started && advanced::AUTOSTART.load(Ordering::Relaxed) {
+        restart()?;
+    } else if !started && !WARNED_NOT_STARTED.swap(true, Ordering::Relaxed) {
+        warn("check! called before start!, timing from program init")?;
+    }
+    let total_time = state.chrono.lock()?.elapsed();
+    let delta = if let Some(last_dur) = *state.last_duration.lock()? {
+        total_time - last_dur
+    } else {
+        total_time
+    };
+    if advance {
+        *state.last_duration.lock()? = Some(total_time);
+    }
+    Ok((total_time, delta))
+}
+
+#[doc(hidden)]
+pub fn emit(line: std::fmt::Arguments) -> std::io::Result<()> {
+    output(render(line))
+}
+
+/// Apply the session prefix and color settings to a line
+fn render(line: std::fmt::Arguments) -> String {
+    let session = advanced::SESSION.with(|s| s.borrow().clone());
+    let line = match session {
+        Some(id) => format!("{} {}", format_args!("[{}]", id).bold(), line),
+        None => line.to_string(),
+    };
+    recolor(line)
+}
+
+/// Apply the color settings to a line
+fn recolor(line: String) -> String {
+    if !advanced::COLOR.load(std::sync::atomic::Ordering::Relaxed) {
+        return strip_ansi(&line);
+    }
+    let line = if advanced::COLOR_TARGETS.lock().unwrap().labels {
+        line
+    } else {
+        // keep the value colors and the resets following them
+        strip_ansi_except(&line, |escape| escape == "[0m" || escape.starts_with("[0;"))
+    };
+    match *advanced::PALETTE.lock().unwrap() {
+        advanced::Palette::TrueColor => line,
+        palette => format::apply_palette(&line, palette),
+    }
+}
+
+thread_local! {
+    // the output of the current `check!(@target, ..)`
+    static REDIRECTED: std::cell::RefCell<Option<Vec<u8>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Write what `print` outputs from the current thread to `target` instead of the output target
+#[doc(hidden)]
+pub fn redirect(
+    target: &mut dyn std::io::Write,
+    print: impl FnOnce() -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let previous = REDIRECTED.with(|redirected| redirected.replace(Some(Vec::new())));
+    let result = print();
+    let bytes = REDIRECTED.with(|redirected| redirected.replace(previous));
+    target.write_all(&bytes.unwrap_or_default())?;
+    result
+}
+
+/// Keep `bytes` for the redirected target if there is one
+fn capture_redirected(bytes: &[u8]) -> bool {
+    REDIRECTED.with(|redirected| match &mut *redirected.borrow_mut() {
+        Some(redirected) => {
+            redirected.extend_from_slice(bytes);
+            true
+        }
+        None => false,
+    })
+}
+
+/// Send a rendered line to the output
+fn output(line: String) -> std::io::Result<()> {
+    // only the binary records are written in binary mode
+    if advanced::format() == advanced::Format::Binary {
+        return Ok(());
+    }
+    if advanced::capture_secondary(&line) {
+        return Ok(());
+    }
+    if capture_redirected(format!("{}\n", line).as_bytes()) {
+        return Ok(());
+    }
+    if let Some(output) = &*advanced::ASYNC_OUTPUT.lock().unwrap() {
+        // the writer thread only goes away when async output is disabled
+        let _ = output.sender.send(advanced::AsyncMessage::Line(line));
+        return Ok(());
+    }
+    advanced::write_out(&line)
+}
+
+/// Write binary data to the output
+fn output_binary(bytes: &[u8]) -> std::io::Result<()> {
+    if advanced::capture_secondary_bytes(bytes) || capture_redirected(bytes) {
+        return Ok(());
+    }
+    advanced::OUTPUT_TARGET.get().write_all(bytes)
+}
+
+fn strip_ansi(s: &str) -> String {
+    strip_ansi_except(s, |_| false)
+}
+
+/// Remove the escape sequences of `s`, except the ones (without their leading ESC) for which `keep` is true
+fn strip_ansi_except(s: &str, keep: impl Fn(&str) -> bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('\x1b') {
+        out.push_str(&rest[..start]);
+        let escape = &rest[start + 1..];
+        // the escape sequence goes up to (and including) its final letter
+        let end = escape
+            .find(|c: char| c.is_ascii_alphabetic())
+            .map_or(escape.len(), |end| end + 1);
+        if keep(&escape[..end]) {
+            out.push('\x1b');
+            out.push_str(&escape[..end]);
         }
+        rest = &escape[end..];
     }
+    out.push_str(rest);
+    out
 }
@@ -0,0 +1,579 @@
+use crate::FDur;
+use once_cell::sync::Lazy;
+use scolor::ColorExt;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// A recorded check (or scope), see [set_recording]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Record {
+    /// The message of the check
+    pub msg: String,
+    /// The elapsed time since the timer started
+    pub total: Duration,
+    /// The elapsed time since the previous check
+    pub delta: Duration,
+    /// The tag of the check, if it had one
+    pub tag: Option<String>,
+    /// The fields of the check, with their values rendered
+    pub fields: Vec<(String, String)>,
+    /// The name (or id if it has none) of the thread that made the check
+    pub thread: String,
+    /// When the check was made
+    pub at: Instant,
+    /// The messages of the [crate::advanced::time_it] scopes the check was made in, outermost first
+    pub stack: Vec<String>,
+    /// If it is the record of a [crate::advanced::time_it] scope rather than a check,
+    /// its total is the duration of the scope and its delta is its self time (excluding the nested scopes)
+    pub scope: bool,
+}
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static RECORDS: Lazy<Mutex<Vec<Record>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Keep a [Record] of every printed check and every ended [crate::advanced::time_it] scope, from all threads
+pub fn set_recording(enabled: bool) {
+    RECORDING.store(enabled, Ordering::Relaxed);
+}
+
+/// A copy of the records made so far
+pub fn records() -> Vec<Record> {
+    RECORDS.lock().unwrap().clone()
+}
+
+/// Take the records made so far, leaving recording enabled with an empty buffer
+///
+/// Useful to handle the records of each logical section (a request, a batch..) separately
+pub fn drain_records() -> Vec<Record> {
+    std::mem::take(&mut *RECORDS.lock().unwrap())
+}
+
+/// Print all the records made so far (from all threads) ordered by the time they were made, with their thread
+pub fn merged_report() {
+    let mut records = records();
+    records.retain(|record| !record.scope);
+    records.sort_by_key(|record| record.at);
+    let Some(first) = records.first().map(|record| record.at) else {
+        return;
+    };
+    for record in &records {
+        if crate::advanced::format() == crate::advanced::Format::Json {
+            let _ = crate::output(
+                crate::format::JsonLine::new("record")
+                    .str("thread", &record.thread)
+                    .num("offset_ns", (record.at - first).as_nanos())
+                    .num("total_ns", record.total.as_nanos())
+                    .num("delta_ns", record.delta.as_nanos())
+                    .str("msg", &record.msg)
+                    .finish(),
+            );
+            continue;
+        }
+        let _ = crate::emit(format_args!(
+            "{} +{} {}{} {} {} {} {}{} {}",
+            record.thread.bold(),
+            FDur(record.at - first, None),
+            "[".light_blue(),
+            "TotalTime:".bold(),
+            FDur(record.total, None),
+            "/".light_blue(),
+            "DeltaTime:".bold(),
+            FDur(record.delta, None),
+            "]".light_blue(),
+            record.msg.light_blue().italic()
+        ));
+    }
+}
+
+pub(crate) fn push(
+    msg: &dyn std::fmt::Display,
+    total: Duration,
+    delta: Duration,
+    opts: &crate::CheckOpts,
+) {
+    let recording = RECORDING.load(Ordering::Relaxed);
+    let hooks = CHECK_HOOKS.read().unwrap();
+    if !recording && hooks.is_empty() {
+        return;
+    }
+    let record = Record::new(msg, total, delta, opts);
+    for hook in hooks.iter() {
+        hook(&record);
+    }
+    if recording {
+        RECORDS.lock().unwrap().push(record);
+    }
+}
+
+impl Record {
+    fn new(
+        msg: &dyn std::fmt::Display,
+        total: Duration,
+        delta: Duration,
+        opts: &crate::CheckOpts,
+    ) -> Self {
+        let thread = std::thread::current();
+        let thread = match thread.name() {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", thread.id()),
+        };
+        Record {
+            msg: msg.to_string(),
+            total,
+            delta,
+            tag: opts.tag.map(Into::into),
+            fields: opts
+                .fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+            thread,
+            at: Instant::now(),
+            stack: crate::advanced::scope_stack(),
+            scope: false,
+        }
+    }
+    pub(crate) fn decoded(msg: String, total: Duration, delta: Duration) -> Self {
+        Record {
+            msg,
+            total,
+            delta,
+            tag: None,
+            fields: Vec::new(),
+            thread: String::new(),
+            at: Instant::now(),
+            stack: Vec::new(),
+            scope: false,
+        }
+    }
+}
+
+/// Record a scope that ended, scopes are only recorded (hooks don't receive them)
+pub(crate) fn push_scope(msg: &dyn std::fmt::Display, total: Duration, self_time: Duration) {
+    if !RECORDING.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut record = Record::new(msg, total, self_time, &Default::default());
+    record.scope = true;
+    RECORDS.lock().unwrap().push(record);
+}
+
+/// Convert the records of scopes to the folded stacks format of flamegraph tools (like `inferno-flamegraph`)
+///
+/// Each scope gives a `outer;inner;scope self_time` line, with its self time in microseconds, checks are left out
+pub fn fold_records(records: &[Record]) -> String {
+    let mut folded = String::new();
+    for record in records.iter().filter(|record| record.scope) {
+        for frame in &record.stack {
+            folded.push_str(&frame.replace(';', ":"));
+            folded.push(';');
+        }
+        folded.push_str(&record.msg.replace(';', ":"));
+        folded.push_str(&format!(" {}\n", record.delta.as_micros()));
+    }
+    folded
+}
+
+/// Convert the records of scopes to a Graphviz graph, where nested scopes are edges from their parent labeled with their duration
+///
+/// Scopes with the same path are merged (their durations are summed), checks are left out.
+/// Render it with `dot -Tsvg`
+pub fn to_dot(records: &[Record]) -> String {
+    // the path (enclosing scopes then own message) of each node, its summed duration and count
+    let mut nodes: Vec<(Vec<&str>, Duration, u32)> = Vec::new();
+    let mut index: HashMap<Vec<&str>, usize> = HashMap::new();
+    for record in records.iter().filter(|record| record.scope) {
+        let mut path: Vec<&str> = Vec::new();
+        for frame in record.stack.iter().chain([&record.msg]) {
+            path.push(frame);
+            index.entry(path.clone()).or_insert_with(|| {
+                nodes.push((path.clone(), Duration::ZERO, 0));
+                nodes.len() - 1
+            });
+        }
+        let node = &mut nodes[index[&path]];
+        node.1 += record.total;
+        node.2 += 1;
+    }
+    let text = |dur: Duration| crate::strip_ansi(&FDur(dur, None).to_string());
+    let escape = |label: &str| label.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut dot = String::from("digraph stime {\n    node [shape=box];\n");
+    for (i, (path, total, count)) in nodes.iter().enumerate() {
+        let name = escape(path.last().copied().unwrap_or_default());
+        let label = match count {
+            0 => name,
+            1 => format!("{}\\n{}", name, text(*total)),
+            _ => format!("{}\\n{} ({} times)", name, text(*total), count),
+        };
+        dot.push_str(&format!("    n{} [label=\"{}\"];\n", i, label));
+        if let Some(parent) = path
+            .len()
+            .checked_sub(1)
+            .and_then(|len| index.get(&path[..len]))
+        {
+            dot.push_str(&format!(
+                "    n{} -> n{} [label=\"{}\"];\n",
+                parent,
+                i,
+                text(*total)
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// A callback receiving each [Record] as it is made, see [on_check] and [on_start]
+pub type Hook = Box<dyn Fn(&Record) + Send + Sync>;
+
+static CHECK_HOOKS: RwLock<Vec<Hook>> = RwLock::new(Vec::new());
+static START_HOOKS: RwLock<Vec<Hook>> = RwLock::new(Vec::new());
+
+/// Call `hook` on every check (checks, spans..), with the same data a [Record] keeps, even when recording is disabled
+///
+/// Several hooks can be registered, they are called in order (a hook can not register other hooks)
+pub fn on_check(hook: Hook) {
+    CHECK_HOOKS.write().unwrap().push(hook);
+}
+
+/// Call `hook` on every [crate::start], its record has zero durations
+///
+/// Several hooks can be registered, they are called in order (a hook can not register other hooks)
+pub fn on_start(hook: Hook) {
+    START_HOOKS.write().unwrap().push(hook);
+}
+
+/// Remove all the hooks registered with [on_check] and [on_start]
+pub fn clear_hooks() {
+    CHECK_HOOKS.write().unwrap().clear();
+    START_HOOKS.write().unwrap().clear();
+}
+
+pub(crate) fn started(msg: &dyn std::fmt::Display) {
+    let hooks = START_HOOKS.read().unwrap();
+    if hooks.is_empty() {
+        return;
+    }
+    let record = Record::new(msg, Duration::ZERO, Duration::ZERO, &Default::default());
+    for hook in hooks.iter() {
+        hook(&record);
+    }
+}
+
+/// Panics if the recorded messages don't contain `phases` in that order (other records can be in between)
+///
+/// Meant for tests, together with [set_recording]
+#[track_caller]
+pub fn assert_phases(phases: &[&str]) {
+    let records = records();
+    let mut remaining = records.iter();
+    for phase in phases {
+        if !remaining.any(|record| record.msg == *phase) {
+            let recorded: Vec<&str> = records.iter().map(|record| record.msg.as_str()).collect();
+            panic!(
+                "phase {:?} is missing or out of order\n  expected: {:?}\n  recorded: {:?}",
+                phase, phases, recorded
+            );
+        }
+    }
+}
+
+/// Panics if the biggest recorded total time exceeds `budget`
+///
+/// Meant for tests, together with [set_recording]
+#[track_caller]
+pub fn assert_within(budget: Duration) {
+    let total = records()
+        .iter()
+        .map(|record| record.total)
+        .max()
+        .unwrap_or_default();
+    if total > budget {
+        panic!("took {:?} which is over the budget of {:?}", total, budget);
+    }
+}
+
+/// Panics if the ratio of the deltas of the recorded phases `a` and `b` (`a / b`) is outside of `range`, see [crate::assert_ratio]
+///
+/// Meant for tests, together with [set_recording]
+#[track_caller]
+pub fn assert_ratio(a: &str, b: &str, range: impl std::ops::RangeBounds<f64> + std::fmt::Debug) {
+    let report = report();
+    let (Some(a_record), Some(b_record)) = (report.phase(a), report.phase(b)) else {
+        let missing = if report.phase(a).is_none() { a } else { b };
+        panic!("phase {:?} was not recorded", missing);
+    };
+    let ratio = a_record.delta.as_secs_f64() / b_record.delta.as_secs_f64();
+    if !range.contains(&ratio) {
+        panic!(
+            "{:?} / {:?} is {:.3} which is outside of {:?}",
+            a, b, ratio, range
+        );
+    }
+}
+
+/// A set of records with some helpers to analyze them, see [report]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunReport {
+    /// The records, in the order they were made
+    pub records: Vec<Record>,
+}
+impl From<Vec<Record>> for RunReport {
+    fn from(records: Vec<Record>) -> Self {
+        Self { records }
+    }
+}
+impl RunReport {
+    /// The records of checks, scopes are left out of the helpers below
+    fn checks(&self) -> impl Iterator<Item = &Record> {
+        self.records.iter().filter(|record| !record.scope)
+    }
+    /// The sum of all the deltas
+    pub fn total(&self) -> Duration {
+        self.checks().map(|record| record.delta).sum()
+    }
+    /// The record with the biggest delta
+    pub fn slowest(&self) -> Option<&Record> {
+        self.checks().max_by_key(|record| record.delta)
+    }
+    /// The first record with the given message
+    pub fn phase(&self, msg: &str) -> Option<&Record> {
+        self.checks().find(|record| record.msg == msg)
+    }
+    /// The sum of the deltas of each tag (untagged records are left out)
+    pub fn sum_by_tag(&self) -> HashMap<String, Duration> {
+        let mut sums = HashMap::new();
+        for record in self.checks() {
+            if let Some(tag) = &record.tag {
+                *sums.entry(tag.clone()).or_default() += record.delta;
+            }
+        }
+        sums
+    }
+}
+
+/// A [RunReport] of the records made so far
+pub fn report() -> RunReport {
+    records().into()
+}
+
+/// The statistics of the deltas of the checks with the same message, see [aggregate]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Aggregate {
+    /// The message of the checks
+    pub msg: String,
+    /// The number of checks
+    pub count: u32,
+    /// The sum of their deltas
+    pub total: Duration,
+    /// The mean of their deltas (rounded down to the nanosecond, [print_aggregate] shows the fraction)
+    pub mean: Duration,
+    /// The biggest of their deltas
+    pub max: Duration,
+}
+
+/// The statistics of the recorded checks grouped by message, sorted by total descending
+///
+/// Meant to find where the time went in a run with many repeated checks, see [print_aggregate]
+pub fn aggregate() -> Vec<Aggregate> {
+    aggregate_of(records())
+}
+
+fn aggregate_of(records: Vec<Record>) -> Vec<Aggregate> {
+    let mut aggregates: Vec<Aggregate> = Vec::new();
+    let mut index = HashMap::new();
+    for record in records.into_iter().filter(|record| !record.scope) {
+        let i = *index.entry(record.msg.clone()).or_insert_with(|| {
+            aggregates.push(Aggregate {
+                msg: record.msg.clone(),
+                count: 0,
+                total: Duration::ZERO,
+                mean: Duration::ZERO,
+                max: Duration::ZERO,
+            });
+            aggregates.len() - 1
+        });
+        let aggregate = &mut aggregates[i];
+        aggregate.count += 1;
+        aggregate.total += record.delta;
+        aggregate.max = aggregate.max.max(record.delta);
+    }
+    for aggregate in &mut aggregates {
+        aggregate.mean = aggregate.total / aggregate.count;
+    }
+    aggregates.sort_by_key(|aggregate| std::cmp::Reverse(aggregate.total));
+    aggregates
+}
+
+/// Print the [aggregate] of the recorded checks as a table
+pub fn print_aggregate() {
+    print_aggregates(&aggregate());
+}
+
+fn print_aggregates(aggregates: &[Aggregate]) {
+    let width = aggregates
+        .iter()
+        .map(|aggregate| aggregate.msg.chars().count())
+        .max()
+        .unwrap_or_default();
+    let _ = crate::advanced::broadcast(|| -> std::io::Result<()> {
+        for aggregate in aggregates {
+            if crate::advanced::format() == crate::advanced::Format::Json {
+                crate::output(
+                    crate::format::JsonLine::new("aggregate")
+                        .str("msg", &aggregate.msg)
+                        .num("count", aggregate.count)
+                        .num("total_ns", aggregate.total.as_nanos())
+                        .num(
+                            "mean_ns",
+                            aggregate.total.as_nanos() as f64 / aggregate.count as f64,
+                        )
+                        .num("max_ns", aggregate.max.as_nanos())
+                        .finish(),
+                )?;
+                continue;
+            }
+            crate::emit(format_args!(
+                "{} {} {:>6} {} {} {} {} {} {}",
+                format!("{:<width$}", aggregate.msg).light_blue().italic(),
+                "count:".bold(),
+                aggregate.count,
+                "total:".bold(),
+                FDur(aggregate.total, None),
+                "mean:".bold(),
+                crate::format::FMean(aggregate.total, aggregate.count.into()),
+                "max:".bold(),
+                FDur(aggregate.max, None)
+            ))?;
+        }
+        Ok(())
+    });
+}
+
+/// Write the [aggregate] of the recorded checks to `path` in the Prometheus text format, for the textfile collector of node_exporter
+///
+/// Each message gives `stime_phase_seconds{phase="parse"} 0.05` (the total of its deltas), `stime_phase_count` and
+/// `stime_phase_max_seconds` samples. The file is written next to `path` then renamed, so a scrape never sees it half written
+pub fn write_prometheus(path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let escape = |label: &str| {
+        label
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    let aggregates = aggregate();
+    let mut text = String::new();
+    let mut metric = |name: &str, help: &str, value: &dyn Fn(&Aggregate) -> String| {
+        text.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} gauge\n",
+            name, help, name
+        ));
+        for aggregate in &aggregates {
+            text.push_str(&format!(
+                "{}{{phase=\"{}\"}} {}\n",
+                name,
+                escape(&aggregate.msg),
+                value(aggregate)
+            ));
+        }
+    };
+    metric(
+        "stime_phase_seconds",
+        "Total time of the checks with this message",
+        &|aggregate| aggregate.total.as_secs_f64().to_string(),
+    );
+    metric(
+        "stime_phase_count",
+        "Number of checks with this message",
+        &|aggregate| aggregate.count.to_string(),
+    );
+    metric(
+        "stime_phase_max_seconds",
+        "Longest check with this message",
+        &|aggregate| aggregate.max.as_secs_f64().to_string(),
+    );
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, text)?;
+    std::fs::rename(&temp, path)
+}
+
+static REPORT_INTERVAL: Mutex<Duration> = Mutex::new(Duration::ZERO);
+static REPORT_THREAD: Mutex<Option<std::thread::Thread>> = Mutex::new(None);
+
+/// Print the [aggregate] of the checks recorded in each window of `interval` from a background thread, for rolling snapshots in long-running programs
+///
+/// The records are drained after each report (see [drain_records]), and recording is enabled.
+/// `Duration::ZERO` stops the reports (so does [crate::advanced::reset_config]), the thread is only started while stime is active
+pub fn set_report_interval(interval: Duration) {
+    let mut reporter = REPORT_THREAD.lock().unwrap();
+    *REPORT_INTERVAL.lock().unwrap() = interval;
+    if interval.is_zero() {
+        if let Some(thread) = &*reporter {
+            thread.unpark();
+        }
+        return;
+    }
+    set_recording(true);
+    match &*reporter {
+        Some(thread) => thread.unpark(),
+        None if crate::active() => {
+            *reporter = Some(std::thread::spawn(report_periodically).thread().clone());
+        }
+        None => {}
+    }
+}
+
+pub(crate) fn report_interval() -> Duration {
+    *REPORT_INTERVAL.lock().unwrap()
+}
+
+fn report_periodically() {
+    let mut last_report = Instant::now();
+    loop {
+        let interval = {
+            let mut reporter = REPORT_THREAD.lock().unwrap();
+            let interval = report_interval();
+            if interval.is_zero() {
+                *reporter = None;
+                return;
+            }
+            interval
+        };
+        let elapsed = last_report.elapsed();
+        if elapsed < interval {
+            std::thread::park_timeout(interval - elapsed);
+            continue;
+        }
+        last_report = Instant::now();
+        let aggregates = aggregate_of(drain_records());
+        if aggregates.is_empty() || !crate::active() {
+            continue;
+        }
+        let _ = crate::advanced::broadcast(|| {
+            if crate::advanced::format() == crate::advanced::Format::Json {
+                crate::output(
+                    crate::format::JsonLine::new("report")
+                        .num("window_ns", elapsed.as_nanos())
+                        .finish(),
+                )
+            } else {
+                crate::emit(format_args!(
+                    "{} {}:",
+                    "Report of the last".bold(),
+                    FDur(elapsed, None)
+                ))
+            }
+        });
+        print_aggregates(&aggregates);
+    }
+}
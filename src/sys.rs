@@ -0,0 +1,61 @@
+//! Platform specific measurements
+
+use std::time::Duration;
+
+/// The CPU time consumed by the calling thread
+#[cfg(target_os = "linux")]
+pub(crate) fn thread_cpu_time() -> Option<Duration> {
+    // the first field is the time spent on the cpu in ns
+    let schedstat = std::fs::read_to_string("/proc/thread-self/schedstat").ok()?;
+    let ns = schedstat.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_nanos(ns))
+}
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+/// The resident set size of the process in bytes
+#[cfg(target_os = "linux")]
+pub(crate) fn rss() -> Option<u64> {
+    use std::ffi::{c_int, c_long};
+    extern "C" {
+        fn sysconf(name: c_int) -> c_long;
+    }
+    const SC_PAGESIZE: c_int = 30;
+    // the second field is the number of resident pages
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { sysconf(SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn rss() -> Option<u64> {
+    None
+}
+
+/// Enable the escape sequences (virtual terminal processing) of the Windows console of stderr
+///
+/// Returns `None` if stderr is not a console, otherwise if the escape sequences are enabled
+#[cfg(windows)]
+pub(crate) fn enable_virtual_terminal() -> Option<bool> {
+    use std::os::windows::io::{AsRawHandle, RawHandle};
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetConsoleMode(console: RawHandle, mode: *mut u32) -> i32;
+        fn SetConsoleMode(console: RawHandle, mode: u32) -> i32;
+    }
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+    let console = std::io::stderr().as_raw_handle();
+    let mut mode = 0;
+    // SAFETY: the handle is the process stderr, and `mode` is a valid pointer
+    if unsafe { GetConsoleMode(console, &mut mode) } == 0 {
+        return None;
+    }
+    if mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0 {
+        return Some(true);
+    }
+    // SAFETY: the handle is the process stderr
+    Some(unsafe { SetConsoleMode(console, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) } != 0)
+}
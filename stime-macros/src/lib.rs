@@ -0,0 +1,66 @@
+#![warn(missing_docs)]
+//! Attribute macros for [stime](https://docs.rs/stime), use them through the `stime` crate
+
+use proc_macro::{Delimiter, Group, Literal, TokenStream, TokenTree};
+
+/// Time a whole function, its duration is printed when it returns (early returns and `?` included)
+///
+/// It is labeled with the function name, or with the given string: `#[stime::timed("parse config")]`
+///
+/// An `async fn` is timed from its first poll until it completes (or is dropped), awaits included,
+/// it isn't a scope so the checks inside it aren't relative to it
+#[proc_macro_attribute]
+pub fn timed(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+
+    let fn_index = tokens
+        .iter()
+        .position(|token| matches!(token, TokenTree::Ident(kw) if kw.to_string() == "fn"))
+        .expect("#[timed] can only be used on functions");
+    let name = match tokens.get(fn_index + 1) {
+        Some(TokenTree::Ident(name)) => name.to_string(),
+        _ => panic!("#[timed] can only be used on functions"),
+    };
+    let is_async = tokens[..fn_index]
+        .iter()
+        .any(|token| matches!(token, TokenTree::Ident(kw) if kw.to_string() == "async"));
+    let label = match attr.into_iter().next() {
+        Some(TokenTree::Literal(lit)) => lit,
+        Some(_) => panic!("#[timed] expects an optional string literal label"),
+        None => Literal::string(&name),
+    };
+
+    let Some(TokenTree::Group(body)) = tokens.pop() else {
+        panic!("#[timed] can only be used on functions with a body");
+    };
+    assert!(
+        body.delimiter() == Delimiter::Brace,
+        "#[timed] can only be used on functions with a body"
+    );
+
+    // the future of an async fn can move between threads and interleave with other tasks,
+    // so it is timed without a scope
+    let guard = if is_async { "time_async" } else { "time_it" };
+    let body: Vec<TokenTree> = body.stream().into_iter().collect();
+    // the inner attributes (`#![allow(..)]`) stay first
+    let inner_attributes = body
+        .chunks(3)
+        .take_while(|attribute| {
+            matches!(attribute, [TokenTree::Punct(hash), TokenTree::Punct(bang), TokenTree::Group(_)]
+                if hash.as_char() == '#' && bang.as_char() == '!')
+        })
+        .count();
+    let mut new_body: TokenStream = body[..3 * inner_attributes].iter().cloned().collect();
+    new_body.extend(
+        format!(
+            "let __stime_timed_guard = ::stime::advanced::{}({});",
+            guard, label
+        )
+        .parse::<TokenStream>()
+        .unwrap(),
+    );
+    new_body.extend(body[3 * inner_attributes..].iter().cloned());
+    tokens.push(TokenTree::Group(Group::new(Delimiter::Brace, new_body)));
+
+    tokens.into_iter().collect()
+}